@@ -0,0 +1,87 @@
+//! Compares the number of instructions generated for each example program at
+//! each optimisation level against the counts recorded from the stack machine
+//! generator.
+//!
+//! Run with `cargo bench --bench instructions`.
+
+extern crate alumina_compiler;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use alumina_compiler::asm::Line;
+use alumina_compiler::folding::Folder;
+use alumina_compiler::generation::Generator;
+use alumina_compiler::lowering::Lowerer;
+use alumina_compiler::optimisation::{self, OptLevel};
+use alumina_compiler::peephole;
+use alumina_compiler::parser::Parser;
+use alumina_compiler::token::Lexer;
+
+const BASELINE: &str = include_str!("stack_machine.txt");
+const OPT_LEVELS: [OptLevel; 3] = [OptLevel::O0, OptLevel::O1, OptLevel::O2];
+
+/// Counts the instructions in generated assembly, skipping labels and directives
+fn count_instructions(lines: &[Line]) -> usize {
+    lines.iter().filter(|line| matches!(line, Line::Instr(..))).count()
+}
+
+fn main() {
+    let baseline: HashMap<&str, usize> = BASELINE.lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.split_once(' '))
+        .map(|(name, count)| (name, count.parse().expect("Baseline counts must be numbers")))
+        .collect();
+
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let mut programs = Vec::new();
+    for dir in [manifest_dir.join("../examples"), manifest_dir.join("tests/programs")] {
+        for entry in fs::read_dir(&dir).expect("Unable to read program directory") {
+            let path = entry.expect("Unable to read directory entry").path();
+            if path.extension().is_some_and(|ext| ext == "alo") {
+                programs.push(path);
+            }
+        }
+    }
+    programs.sort();
+
+    println!("{:<16} {:>8} {:>8} {:>8} {:>8}", "program", "before", "-O0", "-O1", "-O2");
+    let (mut total_before, mut totals) = (0, [0; OPT_LEVELS.len()]);
+    for path in programs {
+        let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+        let source = fs::read_to_string(&path).expect("Unable to read program");
+        let tokens = Lexer::tokenize_str(&source).expect("Unable to lex program");
+        let (mut ast, _) = Parser::parse(tokens.into_iter());
+        Folder::fold(&mut ast).expect("Unable to fold program");
+        let lowered = Lowerer::lower_program(&ast).expect("Unable to lower program");
+
+        let counts = OPT_LEVELS.map(|level| {
+            let mut program = lowered.clone();
+            optimisation::optimise(&mut program, level);
+            let mut lines = Generator::generate_lines(&program);
+            if level != OptLevel::O0 {
+                peephole::optimise(&mut lines);
+            }
+            count_instructions(&lines)
+        });
+        let [o0, o1, o2] = counts;
+        let Some(&before) = baseline.get(name.as_str()) else {
+            println!("{name:<16} {:>8} {o0:>8} {o1:>8} {o2:>8}", "-");
+            continue;
+        };
+        total_before += before;
+        for (total, count) in totals.iter_mut().zip(counts) {
+            *total += count;
+        }
+        println!("{name:<16} {before:>8} {o0:>8} {o1:>8} {o2:>8}");
+    }
+    let [o0, o1, o2] = totals;
+    println!("{:<16} {total_before:>8} {o0:>8} {o1:>8} {o2:>8}", "total");
+    let [o0, o1, o2] = totals.map(|total| format!("{:.1}%", percent_change(total_before, total)));
+    println!("{:<16} {:>8} {o0:>8} {o1:>8} {o2:>8}", "change", "");
+}
+
+fn percent_change(before: usize, after: usize) -> f64 {
+    (after as f64 - before as f64) / before as f64 * 100.0
+}
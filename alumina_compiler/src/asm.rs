@@ -0,0 +1,81 @@
+//! x86-64 assembly as a list of lines, printed in NASM syntax
+//!
+//! The generator builds these rather than text, so that passes like the peephole optimiser can
+//! match on instructions and operands.
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+	Register(&'static str),
+	Immediate(i64),
+	/// A quadword at an offset from the address in a register
+	Memory(&'static str, i64),
+	Label(String)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Line {
+	/// An assembler directive, such as `section .text`
+	Directive(String),
+	Label(String),
+	Instr(&'static str, Vec<Operand>)
+}
+
+impl Operand {
+	pub fn is_memory(&self) -> bool {
+		matches!(self, Operand::Memory(..))
+	}
+}
+
+impl Line {
+	/// Labels an instruction or directive refers to
+	pub fn references(&self) -> Vec<&str> {
+		match self {
+			// Only symbols made visible to or taken from other files, not section names and the like
+			Line::Directive(directive) => match directive.split_once(char::is_whitespace) {
+				Some(("global" | "extern", symbols)) => symbols.split(',').map(str::trim).collect(),
+				_ => Vec::new()
+			},
+			Line::Label(_) => Vec::new(),
+			Line::Instr(_, operands) => operands.iter().filter_map(|operand| match operand {
+				Operand::Label(label) => Some(label.as_str()),
+				_ => None
+			}).collect()
+		}
+	}
+}
+
+impl fmt::Display for Operand {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Operand::Register(reg) => write!(f, "{reg}"),
+			Operand::Immediate(value) => write!(f, "{value}"),
+			Operand::Memory(base, offset) if *offset < 0 => write!(f, "QWORD [{base} - {}]", offset.unsigned_abs()),
+			Operand::Memory(base, 0) => write!(f, "QWORD [{base}]"),
+			Operand::Memory(base, offset) => write!(f, "QWORD [{base} + {offset}]"),
+			Operand::Label(label) => write!(f, "{label}")
+		}
+	}
+}
+
+impl fmt::Display for Line {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Line::Directive(directive) => write!(f, "{directive}"),
+			Line::Label(label) => write!(f, "{label}:"),
+			Line::Instr(mnemonic, operands) => {
+				write!(f, "{mnemonic}")?;
+				for (index, operand) in operands.iter().enumerate() {
+					write!(f, "{}{operand}", if index == 0 { " " } else { ", " })?;
+				}
+				Ok(())
+			}
+		}
+	}
+}
+
+/// Prints lines as the text of an assembly file
+pub fn render(lines: &[Line]) -> String {
+	lines.iter().map(|line| format!("{line}\n")).collect()
+}
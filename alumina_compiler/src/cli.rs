@@ -0,0 +1,158 @@
+use std::path::PathBuf;
+
+use alumina_compiler::optimisation::OptLevel;
+
+pub const USAGE: &str = "\
+Alumina compiler
+
+Usage: alumina-compiler [COMMAND] [OPTIONS] [FILE]...
+
+Commands:
+  build    Compile each file into an executable (default)
+  run      Compile and run a single file
+  check    Check each file for errors without building
+  emit     Print an intermediate form of each file, assembly by default
+
+Files:
+  Use `-` to read source from standard input
+
+Options:
+  -o <path>             Write the output to <path>, only with a single file
+  --emit=<kind>         One of tokens, ast, ast-dot, ir, asm, obj or exe
+  --build-dir <path>    Directory for build artifacts [default: build]
+  --keep-temps          Keep intermediate assembly and object files
+  --interpret           Run without assembling, only with run
+  -O0, -O1, -O2         Optimisation level [default: -O0]
+  -h, --help            Print this message
+
+Text forms (tokens, ast, ast-dot, ir, asm) are printed to standard output unless -o is given.
+";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    Build,
+    Run,
+    Check,
+    Emit
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Emit {
+    Tokens,
+    Ast,
+    /// The AST as a Graphviz digraph
+    AstDot,
+    /// The intermediate representation that assembly is generated from
+    Ir,
+    Asm,
+    Obj,
+    Exe
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub command: Command,
+    /// Source files, where `-` is standard input
+    pub inputs: Vec<String>,
+    pub output: Option<PathBuf>,
+    pub emit: Emit,
+    pub build_dir: PathBuf,
+    pub keep_temps: bool,
+    pub interpret: bool,
+    pub opt_level: OptLevel
+}
+
+impl Options {
+    /// Parses command line arguments, excluding the program name
+    ///
+    /// Returns `None` when help was requested, or an error message for invalid usage
+    pub fn parse(args: &[String]) -> Result<Option<Options>, String> {
+        let mut args = args.iter().peekable();
+
+        let command = match args.peek().map(|arg| arg.as_str()) {
+            None => return Ok(None),
+            Some("build") => Command::Build,
+            Some("run") => Command::Run,
+            Some("check") => Command::Check,
+            Some("emit") => Command::Emit,
+            // Files without a command are built
+            Some(_) => Command::Build,
+        };
+        if matches!(args.peek().map(|arg| arg.as_str()), Some("build" | "run" | "check" | "emit")) {
+            args.next();
+        }
+
+        let mut options = Options {
+            command,
+            inputs: Vec::new(),
+            output: None,
+            emit: if command == Command::Emit { Emit::Asm } else { Emit::Exe },
+            build_dir: PathBuf::from("build"),
+            keep_temps: false,
+            interpret: false,
+            opt_level: OptLevel::O0
+        };
+        let mut emit = None;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-h" | "--help" => return Ok(None),
+                "-o" => {
+                    let path = args.next().ok_or("`-o` requires a path")?;
+                    options.output = Some(PathBuf::from(path));
+                },
+                "--build-dir" => {
+                    let path = args.next().ok_or("`--build-dir` requires a path")?;
+                    options.build_dir = PathBuf::from(path);
+                },
+                "--keep-temps" => options.keep_temps = true,
+                "--interpret" => options.interpret = true,
+                "-O0" => options.opt_level = OptLevel::O0,
+                "-O1" => options.opt_level = OptLevel::O1,
+                "-O2" => options.opt_level = OptLevel::O2,
+                // Older spellings of --emit=tokens and --emit=ast
+                "-tokens" => emit = Some(Emit::Tokens),
+                "-parse-tree" => emit = Some(Emit::Ast),
+                "-" => options.inputs.push(arg.clone()),
+                arg if arg.starts_with("--emit=") => {
+                    emit = Some(match &arg["--emit=".len()..] {
+                        "tokens" => Emit::Tokens,
+                        "ast" => Emit::Ast,
+                        "ast-dot" => Emit::AstDot,
+                        "ir" => Emit::Ir,
+                        "asm" => Emit::Asm,
+                        "obj" => Emit::Obj,
+                        "exe" => Emit::Exe,
+                        kind => return Err(format!("unknown kind `{kind}` for `--emit`, expected one of tokens, ast, ast-dot, ir, asm, obj or exe"))
+                    });
+                },
+                arg if arg.starts_with('-') => return Err(format!("unknown option `{arg}`")),
+                arg => options.inputs.push(arg.to_string()),
+            }
+        }
+
+        if let Some(emit) = emit {
+            if matches!(command, Command::Run | Command::Check) {
+                return Err(String::from("`--emit` can only be used with build or emit"));
+            }
+            options.emit = emit;
+        }
+        if options.interpret && command != Command::Run {
+            return Err(String::from("`--interpret` can only be used with run"));
+        }
+        if options.inputs.is_empty() {
+            return Err(String::from("no input files"));
+        }
+        if command == Command::Run && options.inputs.len() > 1 {
+            return Err(String::from("run takes a single file"));
+        }
+        if options.output.is_some() && options.inputs.len() > 1 {
+            return Err(String::from("`-o` can only be used with a single file"));
+        }
+        if options.inputs.iter().filter(|input| *input == "-").count() > 1 {
+            return Err(String::from("standard input can only be read once"));
+        }
+
+        Ok(Some(options))
+    }
+}
@@ -0,0 +1,289 @@
+use std::num::IntErrorKind;
+
+use crate::folding::{FoldError, FoldWarning};
+use crate::generation::GeneratorError;
+use crate::interpreter::InterpreterError;
+use crate::parser::ParserError;
+use crate::token::{LexerError, Span, Token};
+
+const TAB_WIDTH: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    /// Reported without stopping compilation
+    Warning,
+}
+
+/// A user facing error or warning message, optionally pointing at the source text
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Option<Span>,
+    /// Short message printed under the caret
+    pub label: Option<String>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            message: message.into(),
+            span: None,
+            label: None,
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn warning(message: impl Into<String>) -> Diagnostic {
+        Diagnostic { severity: Severity::Warning, ..Diagnostic::error(message) }
+    }
+
+    pub fn with_span(mut self, span: Span) -> Diagnostic {
+        self.span = Some(span);
+        self
+    }
+
+    pub fn with_label(mut self, label: impl Into<String>) -> Diagnostic {
+        self.label = Some(label.into());
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Diagnostic {
+        self.notes.push(note.into());
+        self
+    }
+
+    /// Renders the diagnostic in the style of rustc
+    ///
+    /// ```text
+    /// error: cannot find variable `b` in this scope
+    ///  --> examples/test.alo:5:6
+    ///   |
+    /// 5 | exit(b)
+    ///   |      ^ not declared
+    ///   |
+    ///   = note: variables must be declared with `let` before they are used
+    /// ```
+    pub fn render(&self, file_name: &str, source: &str) -> String {
+        let (name, colour) = match self.severity {
+            Severity::Error => ("error", "31"),
+            Severity::Warning => ("warning", "33"),
+        };
+        let mut output = format!("\x1b[1;{colour}m{name}\x1b[0m\x1b[1m: {}\x1b[0m\n", self.message);
+
+        let Some(span) = self.span else {
+            // Command line errors have no file to point at
+            if !file_name.is_empty() {
+                output += &format!(" \x1b[1;34m-->\x1b[0m {file_name}\n");
+            }
+            for note in &self.notes {
+                output += &format!("  \x1b[1;34m=\x1b[0m \x1b[1mnote\x1b[0m: {note}\n");
+            }
+            return output;
+        };
+
+        let line_number = span.line.to_string();
+        let gutter = " ".repeat(line_number.len());
+        output += &format!("{gutter}\x1b[1;34m-->\x1b[0m {file_name}:{}:{}\n", span.line, span.column);
+        output += &format!("{gutter} \x1b[1;34m|\x1b[0m\n");
+
+        let line = source.lines().nth(span.line.saturating_sub(1)).unwrap_or("");
+        let (before, rest) = split_at_column(line, span.column);
+        let underlined = source.get(span.offset..span.offset + span.len)
+            .map(|text| text.lines().next().unwrap_or("").chars().count())
+            .unwrap_or(0)
+            .max(1);
+        let underlined = underlined.min(rest.chars().count().max(1));
+
+        output += &format!(
+            "\x1b[1;34m{line_number} |\x1b[0m {}\n",
+            expand_tabs(line)
+        );
+        output += &format!(
+            "{gutter} \x1b[1;34m|\x1b[0m {}\x1b[1;{colour}m{}{}\x1b[0m\n",
+            " ".repeat(display_width(before)),
+            "^".repeat(underlined),
+            self.label.as_ref().map(|label| format!(" {label}")).unwrap_or_default()
+        );
+
+        if !self.notes.is_empty() {
+            output += &format!("{gutter} \x1b[1;34m|\x1b[0m\n");
+        }
+        for note in &self.notes {
+            output += &format!("{gutter} \x1b[1;34m=\x1b[0m \x1b[1mnote\x1b[0m: {note}\n");
+        }
+
+        output
+    }
+}
+
+/// Splits a line before the given 1-indexed column
+fn split_at_column(line: &str, column: usize) -> (&str, &str) {
+    let index = line.char_indices()
+        .nth(column.saturating_sub(1))
+        .map(|(index, _)| index)
+        .unwrap_or(line.len());
+    line.split_at(index)
+}
+
+fn display_width(text: &str) -> usize {
+    text.chars().map(|ch| if ch == '\t' { TAB_WIDTH } else { 1 }).sum()
+}
+
+fn expand_tabs(text: &str) -> String {
+    text.replace('\t', &" ".repeat(TAB_WIDTH))
+}
+
+fn describe_token(token: &Token) -> String {
+    match token {
+        Token::Sep => String::from("end of statement"),
+        Token::Ident(name) => format!("identifier `{name}`"),
+        Token::IntLiteral(value) => format!("integer `{value}`"),
+        Token::LineComment(_) | Token::BlockComment(_) => String::from("comment"),
+        token => format!("`{token}`"),
+    }
+}
+
+impl From<&LexerError> for Diagnostic {
+    fn from(err: &LexerError) -> Diagnostic {
+        match err {
+            LexerError::IntParse(err, span) => {
+                match err.kind() {
+                    IntErrorKind::PosOverflow => literal_out_of_range(*span),
+                    _ => Diagnostic::error("invalid integer literal").with_span(*span).with_label(err.to_string()),
+                }
+            },
+            LexerError::IOError(err) => Diagnostic::error(format!("failed to read source: {err}")),
+            LexerError::InvalidUtf8(span) => Diagnostic::error(format!("invalid UTF-8 at byte {}", span.offset))
+                .with_span(*span)
+                .with_label("not part of a valid character"),
+            LexerError::UnexpectedCharacter(ch, span) => Diagnostic::error(format!("unexpected character `{ch}`"))
+                .with_span(*span)
+                .with_label("not valid in Alumina source"),
+            LexerError::UnterminatedComment(span) => Diagnostic::error("unterminated block comment")
+                .with_span(*span)
+                .with_label("comment opened here")
+                .with_note("block comments nest, so each `/*` needs a matching `*/`"),
+            LexerError::EndOfInput => Diagnostic::error("unexpected end of input"),
+        }
+    }
+}
+
+impl From<&ParserError<'_>> for Diagnostic {
+    fn from(err: &ParserError<'_>) -> Diagnostic {
+        match err {
+            ParserError::UnexpectedToken { expected, found, span } => {
+                let found = match found {
+                    Some(token) => describe_token(token),
+                    None => String::from("end of input"),
+                };
+                Diagnostic::error(format!("expected {expected}, found {found}"))
+                    .with_span(*span)
+                    .with_label(format!("expected {expected}"))
+            },
+            ParserError::LiteralOutOfRange(span) => literal_out_of_range(*span),
+            ParserError::EndOfInput | ParserError::EndOfBlock => Diagnostic::error("unexpected end of input"),
+        }
+    }
+}
+
+fn literal_out_of_range(span: Span) -> Diagnostic {
+    Diagnostic::error("invalid integer literal")
+        .with_span(span)
+        .with_label("literal out of range")
+        .with_note(format!("integer literals range from {} to {}", i64::MIN, i64::MAX))
+}
+
+impl From<&GeneratorError> for Diagnostic {
+    fn from(err: &GeneratorError) -> Diagnostic {
+        match err {
+            GeneratorError::VariableAlreadyDeclared(name, span) => Diagnostic::error(format!("variable `{name}` is already declared"))
+                .with_span(*span)
+                .with_label("declared again here")
+                .with_note(format!("use `{name} = ...` to assign a new value")),
+            GeneratorError::VariableNotYetDeclared(name, span) => Diagnostic::error(format!("cannot find variable `{name}` in this scope"))
+                .with_span(*span)
+                .with_label("not declared")
+                .with_note("variables must be declared with `let` before they are used"),
+            GeneratorError::FunctionAlreadyDeclared(name, span) => Diagnostic::error(format!("function `{name}` is already declared"))
+                .with_span(*span)
+                .with_label("declared again here"),
+            GeneratorError::FunctionNotYetDeclared(name, span) => Diagnostic::error(format!("cannot find function `{name}`"))
+                .with_span(*span)
+                .with_label("not declared")
+                .with_note("functions must be declared with `fn` before they are called"),
+            GeneratorError::ArgumentCountMismatch(name, expected, found, span) => Diagnostic::error(format!("function `{name}` takes {expected} argument(s) but {found} were supplied"))
+                .with_span(*span)
+                .with_label(format!("expected {expected} argument(s)")),
+            GeneratorError::TooManyParameters(name, span) => Diagnostic::error(format!("function `{name}` has too many parameters"))
+                .with_span(*span)
+                .with_note("functions can take at most 6 parameters"),
+            GeneratorError::NestedFunction(name, span) => Diagnostic::error(format!("function `{name}` is not declared at the top level"))
+                .with_span(*span)
+                .with_label("declared inside a block")
+                .with_note("functions can only be declared outside of any block or function"),
+            GeneratorError::ReturnOutsideFunction(span) => Diagnostic::error("`return` outside of a function")
+                .with_span(*span)
+                .with_label("cannot return from here")
+                .with_note("use `exit` to end the program"),
+            GeneratorError::UnexpectedNode(node_type, span) => Diagnostic::error(format!("internal compiler error: unexpected {node_type:?}"))
+                .with_span(*span),
+            GeneratorError::BlockNotYetOpened => Diagnostic::error("internal compiler error: block closed before it was opened"),
+        }
+    }
+}
+
+impl From<&FoldError> for Diagnostic {
+    fn from(err: &FoldError) -> Diagnostic {
+        match err {
+            FoldError::DivisionByZero(span) => Diagnostic::error("this operation will divide by zero at runtime")
+                .with_span(*span)
+                .with_label("divisor is zero"),
+            FoldError::DivisionOverflow(span) => Diagnostic::error("this operation will overflow at runtime")
+                .with_span(*span)
+                .with_label("result is too large")
+                .with_note(format!("{} / -1 does not fit in a 64-bit integer", i64::MIN)),
+        }
+    }
+}
+
+impl From<&FoldWarning> for Diagnostic {
+    fn from(warning: &FoldWarning) -> Diagnostic {
+        match warning {
+            FoldWarning::Overflow(value, span) => Diagnostic::warning("constant arithmetic overflows")
+                .with_span(*span)
+                .with_label(format!("wraps around to {value}")),
+            FoldWarning::DivisionByZero(span) => Diagnostic::warning("this operation will divide by zero if it runs")
+                .with_span(*span)
+                .with_label("divisor is zero"),
+            FoldWarning::DivisionOverflow(span) => Diagnostic::warning("this operation will overflow if it runs")
+                .with_span(*span)
+                .with_label("result is too large")
+                .with_note(format!("{} / -1 does not fit in a 64-bit integer", i64::MIN)),
+        }
+    }
+}
+
+impl From<&InterpreterError> for Diagnostic {
+    fn from(err: &InterpreterError) -> Diagnostic {
+        match err {
+            InterpreterError::DivisionByZero(span) => Diagnostic::error("attempt to divide by zero")
+                .with_span(*span)
+                .with_label("divisor is zero"),
+            InterpreterError::DivisionOverflow(span) => Diagnostic::error("attempt to divide with overflow")
+                .with_span(*span)
+                .with_label("result is too large")
+                .with_note(format!("{} / -1 does not fit in a 64-bit integer", i64::MIN)),
+            InterpreterError::StackOverflow(span) => Diagnostic::error("stack overflow")
+                .with_span(*span)
+                .with_label("too many nested calls"),
+            InterpreterError::UnexpectedNode(node_type, span) => Diagnostic::error(format!("internal interpreter error: unexpected {node_type:?}"))
+                .with_span(*span),
+            InterpreterError::Exit(_) | InterpreterError::Return(_) =>
+                Diagnostic::error("internal interpreter error: unexpected end of program"),
+        }
+    }
+}
@@ -0,0 +1,188 @@
+//! Evaluates constant expressions in the parse tree and removes statements that can never run
+//!
+//! Runs before lowering, so `if` arms and `while` loops that are removed are never checked or lowered.
+//! Constant arithmetic wraps as it does at runtime. A constant division that would trap is an error in
+//! code that runs whenever the program does, and a warning in code that might not, where it is left to
+//! trap at runtime.
+
+use crate::optimisation::{fold_binary, fold_unary};
+use crate::parser::{Ast, BinaryOp, Expr, NodeId, NodeKind, NodeType, Stmt, UnaryOp};
+use crate::token::Span;
+
+#[derive(Debug)]
+pub enum FoldError {
+	DivisionByZero(Span),
+	/// Division of the smallest value by -1, whose result doesn't fit
+	DivisionOverflow(Span)
+}
+
+#[derive(Debug)]
+pub enum FoldWarning {
+	/// Constant arithmetic that wraps around, with the wrapped result
+	Overflow(i64, Span),
+	/// Division by zero in code that might not run
+	DivisionByZero(Span),
+	/// Division of the smallest value by -1 in code that might not run
+	DivisionOverflow(Span)
+}
+
+pub struct Folder<'a> {
+	ast: &'a mut Ast,
+	warnings: Vec<FoldWarning>,
+	/// Depth of the branches, loops, functions and short circuited operands being folded, which
+	/// might not run
+	conditional: usize
+}
+
+impl<'a> Folder<'a> {
+	/// Folds a whole program, returning warnings for constant arithmetic that overflows
+	pub fn fold(ast: &'a mut Ast) -> Result<Vec<FoldWarning>, FoldError> {
+		let root = ast.root;
+		let mut folder = Folder { ast, warnings: Vec::new(), conditional: 0 };
+		folder.fold_statement(root)?;
+		Ok(folder.warnings)
+	}
+
+	fn fold_statement(&mut self, id: NodeId) -> Result<(), FoldError> {
+		match self.ast.variant(id) {
+			NodeType::Block(statements) => {
+				for statement in statements {
+					self.fold_statement(statement)?;
+				}
+			},
+			NodeType::Stmt(Stmt::If { condition, then_block, else_block }) => match self.fold_expr(condition)? {
+				Some(value) => {
+					// Only folds the arm that is kept, so errors in the other aren't reported
+					let taken = if value != 0 { Some(then_block) } else { else_block };
+					self.replace_statement(id, taken);
+					if let Some(taken) = taken {
+						self.fold_statement(taken)?;
+					}
+				},
+				None => {
+					self.fold_conditional(then_block)?;
+					if let Some(else_block) = else_block {
+						self.fold_conditional(else_block)?;
+					}
+				}
+			},
+			NodeType::Stmt(Stmt::While { condition, body }) => match self.fold_expr(condition)? {
+				Some(0) => self.replace_statement(id, None),
+				// The body runs at least once
+				Some(_) => self.fold_statement(body)?,
+				None => self.fold_conditional(body)?
+			},
+			NodeType::Stmt(Stmt::Function { body, .. }) => self.fold_conditional(body)?,
+			NodeType::Stmt(Stmt::Exit(value) | Stmt::Let(_, value) | Stmt::Assign(_, value) | Stmt::Return(value) | Stmt::Expr(value)) => {
+				self.fold_expr(value)?;
+			},
+			NodeType::Expr(_) => ()
+		}
+		Ok(())
+	}
+
+	/// Folds a statement that might not run
+	fn fold_conditional(&mut self, id: NodeId) -> Result<(), FoldError> {
+		self.conditional += 1;
+		let result = self.fold_statement(id);
+		self.conditional -= 1;
+		result
+	}
+
+	/// Reports a constant division that would trap, as an error only if it always runs
+	fn division_traps(&mut self, error: FoldError) -> Result<(), FoldError> {
+		if self.conditional == 0 {
+			return Err(error);
+		}
+		self.warnings.push(match error {
+			FoldError::DivisionByZero(span) => FoldWarning::DivisionByZero(span),
+			FoldError::DivisionOverflow(span) => FoldWarning::DivisionOverflow(span)
+		});
+		Ok(())
+	}
+
+	/// Folds the constant parts of an expression, returning its value if all of it is constant
+	fn fold_expr(&mut self, id: NodeId) -> Result<Option<i64>, FoldError> {
+		let span = self.ast.get(id).span;
+		let value = match self.ast.variant(id) {
+			NodeType::Expr(Expr::Literal(value)) => return Ok(Some(value)),
+			NodeType::Expr(Expr::Call(_, arguments)) => {
+				for argument in arguments {
+					self.fold_expr(argument)?;
+				}
+				return Ok(None);
+			},
+			NodeType::Expr(Expr::Unary(op, operand)) => {
+				let Some(operand) = self.fold_expr(operand)? else { return Ok(None) };
+				if op == UnaryOp::Neg && operand == i64::MIN {
+					self.warnings.push(FoldWarning::Overflow(i64::MIN, span));
+				}
+				fold_unary(op, operand)
+			},
+			NodeType::Expr(Expr::Binary(op @ (BinaryOp::And | BinaryOp::Or), lhs, rhs)) => {
+				let lhs = self.fold_expr(lhs)?;
+				match (op, lhs) {
+					// The right hand side is never evaluated
+					(BinaryOp::And, Some(0)) => 0,
+					(BinaryOp::Or, Some(lhs)) if lhs != 0 => 1,
+					_ => {
+						// Unless the left hand side is constant, the right might not be evaluated
+						let conditional = lhs.is_none() as usize;
+						self.conditional += conditional;
+						let rhs = self.fold_expr(rhs);
+						self.conditional -= conditional;
+						match (lhs, rhs?) {
+							(Some(lhs), Some(rhs)) => fold_binary(op, lhs, rhs).unwrap(),
+							_ => return Ok(None)
+						}
+					}
+				}
+			},
+			NodeType::Expr(Expr::Binary(op, lhs, rhs)) => {
+				let (lhs, rhs) = (self.fold_expr(lhs)?, self.fold_expr(rhs)?);
+				// A division that traps is left in place, to trap if it runs
+				if op == BinaryOp::Div && rhs == Some(0) {
+					self.division_traps(FoldError::DivisionByZero(span))?;
+					return Ok(None);
+				}
+				let (Some(lhs), Some(rhs)) = (lhs, rhs) else { return Ok(None) };
+				let Some(value) = fold_binary(op, lhs, rhs) else {
+					self.division_traps(FoldError::DivisionOverflow(span))?;
+					return Ok(None);
+				};
+				let overflows = match op {
+					BinaryOp::Add => lhs.checked_add(rhs).is_none(),
+					BinaryOp::Sub => lhs.checked_sub(rhs).is_none(),
+					BinaryOp::Mul => lhs.checked_mul(rhs).is_none(),
+					_ => false
+				};
+				if overflows {
+					self.warnings.push(FoldWarning::Overflow(value, span));
+				}
+				value
+			},
+			NodeType::Expr(Expr::Ident(_)) | NodeType::Block(_) | NodeType::Stmt(_) => return Ok(None)
+		};
+
+		// Becomes a literal in place, so its parent doesn't need to change
+		for child in self.ast.children(id) {
+			self.ast.tree.remove_subtree(child);
+		}
+		self.ast.tree[id].kind = NodeKind::Literal(value);
+		Ok(Some(value))
+	}
+
+	/// Puts one of a statement's own blocks in its place, or removes it if there is none
+	fn replace_statement(&mut self, id: NodeId, replacement: Option<NodeId>) {
+		match replacement {
+			Some(replacement) => {
+				// Can't take the place of its own ancestor while it is still attached to it
+				self.ast.tree.detach(replacement);
+				self.ast.tree.replace_with(id, replacement);
+			},
+			None => {
+				self.ast.tree.remove_subtree(id);
+			}
+		}
+	}
+}
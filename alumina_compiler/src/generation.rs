@@ -1,4 +1,3 @@
-
 /* Minimum req.
 
 global _start
@@ -14,307 +13,586 @@ win   -:
 	? ? ?
 */
 
-use std::iter::Peekable;
+use std::collections::HashSet;
+use std::convert::TryFrom;
 
-use crate::parser::{Node, NodeType};
+use crate::asm::{self, Line, Operand as AsmOperand};
+use crate::ir::{Function, Instr, Operand, Program, Terminator, VReg};
+use crate::lowering::Lowerer;
+use crate::parser::{Ast, BinaryOp, NodeKind, UnaryOp};
+use crate::ssa;
+use crate::token::Span;
 
 #[derive(Debug)]
 pub enum GeneratorError {
-	EndOfInput,
-	VariableAlreadyDeclared(String),
-	VariableNotYetDeclared(String),
+	VariableAlreadyDeclared(String, Span),
+	VariableNotYetDeclared(String, Span),
+	FunctionAlreadyDeclared(String, Span),
+	FunctionNotYetDeclared(String, Span),
+	/// Function name, expected and found argument counts
+	ArgumentCountMismatch(String, usize, usize, Span),
+	TooManyParameters(String, Span),
+	/// Functions may only be defined at the top level of a program
+	NestedFunction(String, Span),
+	ReturnOutsideFunction(Span),
 	BlockNotYetOpened,
-	UnexpectedNode(NodeType)
+	UnexpectedNode(NodeKind, Span)
 }
 
-pub struct Generator<I: Iterator<Item = Node>> {
-	input: Peekable<I>,
-	variables: Vec<(String, usize)>,
-	stack_size: usize,
-	label_count: usize,
-	scopes: Vec<usize>,
-	output: String
+/// Registers used for arguments by the System V AMD64 calling convention, in order
+pub(crate) const ARGUMENT_REGISTERS: [&str; 6] = ["rdi", "rsi", "rdx", "rcx", "r8", "r9"];
+/// Registers that virtual registers are allocated to, in the order they are handed out
+///
+/// `rax` and `rdx` are left out as division and calls overwrite them
+const ALLOCATABLE_REGISTERS: [&str; 11] = ["rbx", "r12", "r13", "r14", "r15", "r10", "rsi", "rdi", "rcx", "r8", "r9"];
+/// Registers the System V AMD64 calling convention has a function preserve for its caller
+///
+/// Functions push those they use on entry and pop them before returning, the others are saved by
+/// the caller around each call
+const CALLEE_SAVED_REGISTERS: [&str; 5] = ["rbx", "r12", "r13", "r14", "r15"];
+/// Holds a constant operand too large for an immediate
+const SCRATCH_REGISTER: &str = "r11";
+
+/// Where a virtual register lives for the whole of its function
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Location {
+	Register(&'static str),
+	/// Spill slot below the frame pointer
+	Stack(usize)
 }
 
-impl <I: Iterator<Item = Node>> Generator<I> {
-	pub fn generate_program(iterator: I) -> Result<String, GeneratorError> {
+impl Location {
+	fn operand(self) -> AsmOperand {
+		match self {
+			Location::Register(reg) => AsmOperand::Register(reg),
+			Location::Stack(slot) => AsmOperand::Memory("rbp", -8 * (slot as i64 + 1))
+		}
+	}
+}
 
-		let input = iterator.peekable();
+/// Positions from the first definition of a virtual register to its last use
+///
+/// Each instruction reads its operands at an even position and writes its result at the following odd one
+#[derive(Debug, Clone, Copy)]
+struct Interval {
+	reg: VReg,
+	start: usize,
+	end: usize
+}
 
-		let mut generator = Generator {
-			input,
-			variables: Vec::new(),
-			stack_size: 0,
-			label_count: 0,
-			scopes: Vec::new(),
-			output: String::new(),
-		};
+pub struct Generator<'a> {
+	function: &'a Function,
+	intervals: Vec<Option<Interval>>,
+	locations: Vec<Option<Location>>,
+	/// Position of each block's first instruction
+	block_starts: Vec<usize>,
+	/// Number of the first block's label, as labels are shared by the whole program
+	label_base: usize,
+	/// Spill slots below rbp, which is only set up if there are any
+	slots: usize,
+	/// Values pushed on top of the frame, for keeping calls aligned
+	pushed: usize,
+	/// Callee saved registers the function uses, pushed on entry in this order
+	callee_saved: Vec<&'static str>,
+	output: Vec<Line>
+}
 
-		loop {
-			match generator.generate_node() {
-				Ok(_) => (),
-				Err(GeneratorError::EndOfInput) => break,
-				Err(err) => return Err(err),
-			}
+impl <'a> Generator<'a> {
+	pub fn generate_program(ast: &'a Ast) -> Result<String, GeneratorError> {
+		let program = Lowerer::lower_program(ast)?;
+		Ok(Generator::generate(&program))
+	}
+
+	/// Generates the text of x86-64 assembly for a lowered program, which may be in SSA form
+	pub fn generate(program: &Program) -> String {
+		asm::render(&Generator::generate_lines(program))
+	}
+
+	/// Generates x86-64 assembly for a lowered program as a list of lines
+	pub fn generate_lines(program: &Program) -> Vec<Line> {
+		let mut output = vec![Line::Directive(String::from("global _start")), Line::Directive(String::from("section .text"))];
+		let mut label_base = 0;
+		for function in std::iter::once(&program.main).chain(&program.functions) {
+			let mut function = function.clone();
+			ssa::destruct(&mut function);
+			let mut generator = Generator::new(&function, label_base);
+			generator.generate_function();
+			output.append(&mut generator.output);
+			label_base += function.blocks.len();
 		}
-		generator.output = String::from("global _start\nsection .text\n_start:\n") + &generator.output + "mov rdi, 0\nmov rax, 60\nsyscall";
-		
-		Ok(generator.output)
+		output
+	}
 
+	fn new(function: &'a Function, label_base: usize) -> Generator<'a> {
+		let (intervals, block_starts) = live_intervals(function);
+		let (locations, slots) = allocate_registers(&intervals);
+		Generator {
+			function,
+			intervals,
+			locations,
+			block_starts,
+			label_base,
+			slots,
+			pushed: 0,
+			callee_saved: Vec::new(),
+			output: Vec::new()
+		}
 	}
 
-	fn push(&mut self, reg: &str) {
-		self.output += &format!("push {}\n", reg);
-		self.stack_size += 1;
+	fn emit(&mut self, mnemonic: &'static str, operands: impl IntoIterator<Item = AsmOperand>) {
+		self.output.push(Line::Instr(mnemonic, operands.into_iter().collect()));
 	}
 
-	fn pop(&mut self, reg: &str) {
-		self.output += &format!("pop {}\n", reg);
-		self.stack_size -= 1;
+	fn label(&self, block: usize) -> String {
+		format!(".bb{}", self.label_base + block)
 	}
 
-	fn create_label(&mut self, name: &str) -> String {
-		let label = format!(".{}{}", name, self.label_count);
-		self.label_count += 1;
-		label
+	/// An operand, which may be an immediate of any size
+	fn operand(&self, operand: Operand) -> AsmOperand {
+		match operand {
+			Operand::Reg(reg) => self.location(reg).operand(),
+			Operand::Const(value) => AsmOperand::Immediate(value)
+		}
 	}
 
-	fn generate_node(&mut self) -> Result<(), GeneratorError> {
-		let node = self.input.peek().ok_or(GeneratorError::EndOfInput)?;
-		match &node.variant {
-			NodeType::StmtFunction(_) => self.generate_function()?,
-			NodeType::StmtIf(_) => self.generate_conditional()?,
-			NodeType::StmtWhile => self.generate_loop()?,
-			NodeType::StmtNewVar(_) => self.generate_variable()?,
-			NodeType::StmtAssign(_) => self.generate_assignment()?,
-			node_type => return Err(GeneratorError::UnexpectedNode(node_type.clone()))
-		}
-		Ok(())
+	fn location(&self, reg: VReg) -> Location {
+		// Registers that are never written only appear in code that can't be reached
+		self.locations[reg.0].unwrap_or(Location::Register(SCRATCH_REGISTER))
 	}
 
-	fn generate_block(&mut self) -> Result<(), GeneratorError> {
-		match self.input.next().ok_or(GeneratorError::EndOfInput)?.variant {
-			NodeType::BlockStart => (),
-			node_type => return Err(GeneratorError::UnexpectedNode(node_type))
-		};
-		
-		self.scopes.push(self.variables.len());
+	fn is_memory(&self, operand: Operand) -> bool {
+		matches!(operand, Operand::Reg(reg) if matches!(self.location(reg), Location::Stack(_)))
+	}
 
-		while let Some(node) = self.input.peek() {
-			if let NodeType::BlockEnd = node.variant {
-				break;
-			}
-			self.generate_node()?;
+	/// Operand for an arithmetic instruction, which only takes 32 bit immediates
+	fn source(&mut self, operand: Operand) -> AsmOperand {
+		match operand {
+			Operand::Const(value) if !fits_immediate(value) => {
+				self.emit("mov", [AsmOperand::Register(SCRATCH_REGISTER), AsmOperand::Immediate(value)]);
+				AsmOperand::Register(SCRATCH_REGISTER)
+			},
+			operand => self.operand(operand)
 		}
-		self.input.next();
-
-		let block_start = self.scopes.pop()
-			.ok_or(GeneratorError::BlockNotYetOpened)?;
-		let pop_count = self.variables.len() - block_start;
+	}
 
-		self.output += &format!("add rsp, {}\n", pop_count * 8);
-		self.stack_size -= pop_count;
-		self.variables.truncate(block_start);	
+	fn generate_move(&mut self, dest: Location, src: Operand) {
+		let (dest_operand, src_operand) = (dest.operand(), self.operand(src));
+		if dest_operand == src_operand {
+			return;
+		}
+		let through_register = matches!(dest, Location::Stack(_))
+			&& (self.is_memory(src) || matches!(src, Operand::Const(value) if !fits_immediate(value)));
+		if through_register {
+			self.emit("mov", [AsmOperand::Register("rax"), src_operand]);
+			self.emit("mov", [dest_operand, AsmOperand::Register("rax")]);
+		} else {
+			self.emit("mov", [dest_operand, src_operand]);
+		}
+	}
 
-		Ok(())
+	/// Moves values into registers at once, where a register may be both read and written
+	///
+	/// Each move goes once nothing still needs to read its destination, and cycles are broken through rax
+	fn generate_parallel_move(&mut self, mut moves: Vec<(AsmOperand, AsmOperand)>) {
+		moves.retain(|(dest, src)| dest != src);
+		while !moves.is_empty() {
+			let ready = moves.iter().position(|(dest, _)| !moves.iter().any(|(_, src)| src == dest));
+			match ready {
+				Some(index) => {
+					let (dest, src) = moves.remove(index);
+					self.emit("mov", [dest, src]);
+				},
+				None => {
+					let blocked = moves[0].0.clone();
+					self.emit("mov", [AsmOperand::Register("rax"), blocked.clone()]);
+					for (_, src) in &mut moves {
+						if *src == blocked {
+							*src = AsmOperand::Register("rax");
+						}
+					}
+				}
+			}
+		}
 	}
 
-	fn generate_variable(&mut self) -> Result<(), GeneratorError> {
-		let node = self.input.next().ok_or(GeneratorError::EndOfInput)?;
-		let name = match node.variant {
-			NodeType::StmtNewVar(name) => name,
-			node_type => return Err(GeneratorError::UnexpectedNode(node_type))
-		};
+	/// Generates a function following the System V AMD64 calling convention
+	///
+	/// Arguments are moved from their registers to wherever their virtual registers were allocated,
+	/// and the callee saved registers the function uses are restored before it returns
+	fn generate_function(&mut self) {
+		let function = self.function;
+		let is_main = function.name == "_start";
+		if is_main {
+			self.output.push(Line::Label(String::from("_start")));
+		} else {
+			self.output.push(Line::Label(format!("fn_{}", function.name)));
+		}
 
-		if self.variables.iter().any(|(str, _)| str == &name) {
-			return Err(GeneratorError::VariableAlreadyDeclared(name.clone()));
+		// The program never returns from `_start`, so it has nothing to preserve
+		if !is_main {
+			self.callee_saved = CALLEE_SAVED_REGISTERS.iter().copied()
+				.filter(|reg| self.locations.contains(&Some(Location::Register(reg))))
+				.collect();
+		}
+		for reg in self.callee_saved.clone() {
+			self.emit("push", [AsmOperand::Register(reg)]);
 		}
 
-		self.generate_expr()?;
-		self.output.pop(); self.output += &format!("	; variable ({}) assigned\n", name);
+		let saved = self.callee_saved.len();
+		if self.slots > 0 {
+			if !is_main {
+				self.emit("push", [AsmOperand::Register("rbp")]);
+			}
+			// Keeps rsp 16 byte aligned, as it was on entry once rbp and an even number of registers are pushed
+			let words = (self.slots + saved).next_multiple_of(2) - saved;
+			self.emit("mov", [AsmOperand::Register("rbp"), AsmOperand::Register("rsp")]);
+			self.emit("sub", [AsmOperand::Register("rsp"), AsmOperand::Immediate(words as i64 * 8)]);
+		} else if !is_main {
+			// The return address and saved registers are on top of the stack
+			self.pushed = 1 + saved;
+		}
 
-		self.variables.push((name.to_owned(), self.stack_size));
+		let live_params: Vec<(AsmOperand, AsmOperand)> = function.params.iter().zip(ARGUMENT_REGISTERS)
+			.filter(|(param, _)| self.intervals[param.0].is_some_and(|interval| interval.start == 0))
+			.map(|(param, reg)| (self.location(*param).operand(), AsmOperand::Register(reg)))
+			.collect();
+		self.generate_parallel_move(live_params);
 
-		Ok(())
+		for (index, block) in function.blocks.iter().enumerate() {
+			self.output.push(Line::Label(self.label(index)));
+			for (offset, instr) in block.instrs.iter().enumerate() {
+				self.generate_instr(instr, self.block_starts[index] + offset * 2);
+			}
+			self.generate_terminator(&block.terminator, index);
+		}
 	}
-	fn generate_assignment(&mut self) -> Result<(), GeneratorError> {
-		let node = self.input.next().ok_or(GeneratorError::EndOfInput)?;
-		let name = match node.variant {
-			NodeType::StmtAssign(name) => name,
-			node_type => return Err(GeneratorError::UnexpectedNode(node_type))
-		};
-		let var_index = match self.variables.iter().find(|(str, _)| str == &name) {
-			Some(var) => var.1,
-			None => return Err(GeneratorError::VariableNotYetDeclared(name))
-		};
 
-		self.generate_expr()?;
-
-		if self.stack_size - var_index > 0 {
-			self.pop("rax");
+	fn generate_instr(&mut self, instr: &Instr, position: usize) {
+		let dest = self.location(instr.dest());
+		match instr {
+			Instr::Copy { src, .. } => self.generate_move(dest, *src),
+			Instr::Unary { op, operand, .. } => self.generate_unary(*op, dest, *operand),
+			Instr::Binary { op: op @ (BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul), lhs, rhs, .. } => self.generate_bin_expr(*op, dest, *lhs, *rhs),
+			Instr::Binary { op: BinaryOp::Div, lhs, rhs, .. } => self.generate_division(dest, *lhs, *rhs),
+			Instr::Binary { op, lhs, rhs, .. } => self.generate_comparison(*op, dest, *lhs, *rhs),
+			Instr::Call { function, arguments, .. } => self.generate_call(function, arguments, dest, position),
+			Instr::Phi { .. } => unreachable!("Phis are replaced with copies before generating")
+		}
+	}
 
-			self.output += &format!(
-				r"mov QWORD [rsp + {}], rax{}",
-				(self.stack_size - var_index) * 8,
-				"\n"
-			);
-		} 
-		
-		Ok(())
+	/// Register to compute a result in, the destination unless it is in memory or read after being overwritten
+	fn work_register(&self, dest: Location, later_operand: Option<Operand>) -> &'static str {
+		match dest {
+			Location::Register(reg) if later_operand.is_none_or(|operand| self.operand(operand) != AsmOperand::Register(reg)) => reg,
+			_ => "rax"
+		}
 	}
 
-	fn generate_expr(&mut self) -> Result<(), GeneratorError> {
-		loop {
-			let node = match self.input.peek() {
-				Some(node) => node,
-				None => break
-			};
-			match &node.variant {
-				NodeType::ExprIdent(name) => {
-					let var_index = match self.variables.iter().find(|(str, _)| str == name) {
-						Some(var) => var.1,
-						None => return Err(GeneratorError::VariableNotYetDeclared(name.clone()))
-					};
-					self.push(&format!(r"QWORD [rsp + {}]", (self.stack_size - var_index) * 8));
-				},
-				NodeType::ExprLiteral(num) => {
-					self.output += &format!("mov rax, {}\n", num);
-					self.push("rax");
-				},
-				NodeType::ExprParen => {
-					self.push("rax");
-				}
-				NodeType::ExprBinAdd => self.generate_bin_expr()?,
-				NodeType::ExprBinSub => self.generate_bin_expr()?,
-				NodeType::ExprBinMul => self.generate_bin_expr()?,
-				NodeType::ExprBinDiv => self.generate_bin_expr()?,
-				_ => break
-			};	
-			self.input.next();
+	fn store_result(&mut self, dest: Location, reg: &'static str) {
+		if dest != Location::Register(reg) {
+			self.emit("mov", [dest.operand(), AsmOperand::Register(reg)]);
 		}
+	}
 
-		Ok(())
+	fn generate_unary(&mut self, op: UnaryOp, dest: Location, operand: Operand) {
+		match op {
+			UnaryOp::Neg => {
+				let reg = self.work_register(dest, None);
+				self.generate_move(Location::Register(reg), operand);
+				self.emit("neg", [AsmOperand::Register(reg)]);
+				self.store_result(dest, reg);
+			},
+			UnaryOp::Not => {
+				let operand = self.compared(operand, false);
+				self.emit("cmp", [operand, AsmOperand::Immediate(0)]);
+				self.generate_flag(dest, "sete");
+			}
+		}
 	}
 
-	fn generate_bin_expr(&mut self) -> Result<(), GeneratorError> {
-		let node_type = self.input.peek().ok_or(GeneratorError::EndOfInput)?.variant.clone();
-		
-		self.pop("rbx");
-		
-		self.pop("rax");
-
-		self.output += match node_type {
-			NodeType::ExprBinAdd => "add rax, rbx\n",
-			NodeType::ExprBinSub => "sub rax, rbx\n",
-			NodeType::ExprBinMul => "mul rbx\n",
-			NodeType::ExprBinDiv => "div rbx\n",
-			node_type => unreachable!("Attempted to generate binary expression with {:?}", node_type)
+	fn generate_bin_expr(&mut self, op: BinaryOp, dest: Location, lhs: Operand, rhs: Operand) {
+		let instr = match op {
+			BinaryOp::Add => "add",
+			BinaryOp::Sub => "sub",
+			BinaryOp::Mul => "imul",
+			op => unreachable!("Attempted to generate binary expression with {:?}", op)
 		};
 
-		self.push("rax");
+		// The result can go straight into the right operand when the order doesn't matter
+		if let Location::Register(reg) = dest {
+			if op != BinaryOp::Sub && self.operand(rhs) == AsmOperand::Register(reg) {
+				let lhs = self.source(lhs);
+				self.emit(instr, [AsmOperand::Register(reg), lhs]);
+				return;
+			}
+		}
 
-		Ok(())
+		let reg = self.work_register(dest, Some(rhs));
+		self.generate_move(Location::Register(reg), lhs);
+		let rhs = self.source(rhs);
+		self.emit(instr, [AsmOperand::Register(reg), rhs]);
+		self.store_result(dest, reg);
 	}
 
-	fn generate_conditional_jump(&mut self, label: &String) -> Result<(), GeneratorError> {
-
-		// https://www.philadelphia.edu.jo/academics/qhamarsheh/uploads/Lecture 18 Conditional Jumps Instructions.pdf
-		self.generate_expr()?;
-
-		let node = self.input.next().ok_or(GeneratorError::EndOfInput)?;
-
-		self.pop("rbx");
-		
-		self.pop("rax");
-
-		self.output += "cmp rax, rbx\n";
-
-		// Follow operations are inverted for usage as expected
-		self.output += &match node.variant {
-			NodeType::ExprNotEqual => format!("je {label}\n"),
-			NodeType::ExprEqual => format!("jne {label}\n"),
-			NodeType::ExprGreater => format!("jna {label}\n"),
-			NodeType::ExprGreaterEqual => format!("jnae {label}\n"),
-			NodeType::ExprLess => format!("jnb {label}\n"),
-			NodeType::ExprLessEqual => format!("jnbe {label}\n"),
-			node_type => return Err(GeneratorError::UnexpectedNode(node_type)),
+	fn generate_division(&mut self, dest: Location, lhs: Operand, rhs: Operand) {
+		self.generate_move(Location::Register("rax"), lhs);
+		// Sign extend rax into rdx:rax for the dividend
+		self.emit("cqo", []);
+		let divisor = match rhs {
+			Operand::Const(value) => {
+				self.emit("mov", [AsmOperand::Register(SCRATCH_REGISTER), AsmOperand::Immediate(value)]);
+				AsmOperand::Register(SCRATCH_REGISTER)
+			},
+			rhs => self.operand(rhs)
 		};
-		
-		// self.output += &format!("jz {}\n", label);
+		self.emit("idiv", [divisor]);
+		self.store_result(dest, "rax");
+	}
 
-		Ok(())
+	/// Left side of a comparison, which has to be in a register or memory
+	fn compared(&mut self, operand: Operand, other_in_memory: bool) -> AsmOperand {
+		if matches!(operand, Operand::Const(_)) || (other_in_memory && self.is_memory(operand)) {
+			self.generate_move(Location::Register("rax"), operand);
+			return AsmOperand::Register("rax");
+		}
+		self.operand(operand)
 	}
 
-	fn generate_function(&mut self) -> Result<(), GeneratorError> {
-		let name = match self.input.next().ok_or(GeneratorError::EndOfInput)?.variant {
-			NodeType::StmtFunction(name) => name,
-			node_type => return Err(GeneratorError::UnexpectedNode(node_type))
+	/// Generates a comparison, producing 1 if it holds and 0 otherwise
+	fn generate_comparison(&mut self, op: BinaryOp, dest: Location, lhs: Operand, rhs: Operand) {
+		// https://www.philadelphia.edu.jo/academics/qhamarsheh/uploads/Lecture 18 Conditional Jumps Instructions.pdf
+		let set = match op {
+			BinaryOp::Equal => "sete",
+			BinaryOp::NotEqual => "setne",
+			BinaryOp::Greater => "setg",
+			BinaryOp::GreaterEqual => "setge",
+			BinaryOp::Less => "setl",
+			BinaryOp::LessEqual => "setle",
+			op => unreachable!("Attempted to generate comparison with {:?}", op)
 		};
+		let lhs = self.compared(lhs, self.is_memory(rhs));
+		let rhs = self.source(rhs);
+		self.emit("cmp", [lhs, rhs]);
+		self.generate_flag(dest, set);
+	}
 
-		self.generate_expr()?;
+	/// Stores 1 in the destination if the flags meet the condition of a `set` instruction, otherwise 0
+	fn generate_flag(&mut self, dest: Location, set: &'static str) {
+		let reg = self.work_register(dest, None);
+		self.emit(set, [AsmOperand::Register("al")]);
+		self.emit("movzx", [AsmOperand::Register(reg), AsmOperand::Register("al")]);
+		self.store_result(dest, reg);
+	}
 
-		if name == "exit" {
-			self.pop("rdi");
-			self.output += "mov rax, 60\n";
-			self.output += "syscall\n";
+	/// Generates a call, moving its arguments into registers
+	///
+	/// Caller saved registers holding values still needed afterwards are saved on the stack around
+	/// the call, as the callee may overwrite them
+	fn generate_call(&mut self, name: &str, arguments: &[Operand], dest: Location, position: usize) {
+		let saved: Vec<&'static str> = self.intervals.iter().flatten()
+			.filter(|interval| interval.start <= position && interval.end > position + 1)
+			.filter_map(|interval| match self.location(interval.reg) {
+				Location::Register(reg) if !CALLEE_SAVED_REGISTERS.contains(&reg) => Some(reg),
+				_ => None
+			})
+			.collect();
+		for reg in &saved {
+			self.emit("push", [AsmOperand::Register(reg)]);
+			self.pushed += 1;
 		}
 
-		Ok(())
-	}
-
-	fn generate_conditional(&mut self) -> Result<(), GeneratorError> {
-		let paths = match self.input.next().ok_or(GeneratorError::EndOfInput)?.variant {
-			NodeType::StmtIf(paths) => paths,
-			node_type => return Err(GeneratorError::UnexpectedNode(node_type))
-		};
-		let label = self.create_label("if");
+		let moves = ARGUMENT_REGISTERS.iter().zip(arguments)
+			.map(|(reg, argument)| (AsmOperand::Register(reg), self.operand(*argument)))
+			.collect();
+		self.generate_parallel_move(moves);
+
+		// The stack must be 16 byte aligned at every call
+		let function = AsmOperand::Label(format!("fn_{}", name));
+		if self.pushed % 2 == 1 {
+			self.emit("sub", [AsmOperand::Register("rsp"), AsmOperand::Immediate(8)]);
+			self.emit("call", [function]);
+			self.emit("add", [AsmOperand::Register("rsp"), AsmOperand::Immediate(8)]);
+		} else {
+			self.emit("call", [function]);
+		}
 
-		self.generate_conditional_jump(&label)?;
+		for reg in saved.iter().rev() {
+			self.emit("pop", [AsmOperand::Register(reg)]);
+			self.pushed -= 1;
+		}
+		self.store_result(dest, "rax");
+	}
 
-		self.generate_block()?;
-		
-		// No else
-		if paths == 0 {
-			self.output += &format!("{}:\n", label);
-			return Ok(())
+	fn generate_terminator(&mut self, terminator: &Terminator, block: usize) {
+		let next = block + 1;
+		match terminator {
+			Terminator::Jump(target) => {
+				if target.0 != next {
+					self.emit("jmp", [AsmOperand::Label(self.label(target.0))]);
+				}
+			},
+			Terminator::Branch { condition: Operand::Const(value), then, otherwise } => {
+				let target = if *value != 0 { then } else { otherwise };
+				if target.0 != next {
+					self.emit("jmp", [AsmOperand::Label(self.label(target.0))]);
+				}
+			},
+			Terminator::Branch { condition, then, otherwise } => {
+				self.emit("cmp", [self.operand(*condition), AsmOperand::Immediate(0)]);
+				if then.0 == next {
+					self.emit("je", [AsmOperand::Label(self.label(otherwise.0))]);
+				} else {
+					self.emit("jne", [AsmOperand::Label(self.label(then.0))]);
+					if otherwise.0 != next {
+						self.emit("jmp", [AsmOperand::Label(self.label(otherwise.0))]);
+					}
+				}
+			},
+			Terminator::Return(value) => {
+				self.generate_move(Location::Register("rax"), *value);
+				if self.slots > 0 {
+					self.emit("mov", [AsmOperand::Register("rsp"), AsmOperand::Register("rbp")]);
+					self.emit("pop", [AsmOperand::Register("rbp")]);
+				}
+				for reg in self.callee_saved.clone().into_iter().rev() {
+					self.emit("pop", [AsmOperand::Register(reg)]);
+				}
+				self.emit("ret", []);
+			},
+			Terminator::Exit(value) => {
+				self.generate_move(Location::Register("rdi"), *value);
+				self.emit("mov", [AsmOperand::Register("rax"), AsmOperand::Immediate(60)]);
+				self.emit("syscall", []);
+			}
 		}
-		let label_else = self.create_label("else");
-		
-		self.output += &format!("jmp {}\n", label_else);
-		self.output += &format!("{}:\n", label);
+	}
+}
 
-		self.generate_block()?;
+/// Whether a constant can be an operand of arithmetic instructions, which sign extend 32 bit immediates
+fn fits_immediate(value: i64) -> bool {
+	i32::try_from(value).is_ok()
+}
 
-		self.output += &format!("{}:\n", label_else);
+/// Finds the interval each virtual register is live over, and the position each block starts at
+///
+/// Intervals are a single range covering every block a register is live in, found by the usual
+/// backwards dataflow over the blocks
+fn live_intervals(function: &Function) -> (Vec<Option<Interval>>, Vec<usize>) {
+	let blocks = &function.blocks;
+	let regs = |operands: Vec<Operand>| operands.into_iter().filter_map(|operand| match operand {
+		Operand::Reg(reg) => Some(reg),
+		Operand::Const(_) => None
+	});
+
+	// Registers read before being written in each block, and those written
+	let mut uses = vec![HashSet::new(); blocks.len()];
+	let mut defs = vec![HashSet::new(); blocks.len()];
+	for (index, block) in blocks.iter().enumerate() {
+		for instr in &block.instrs {
+			for reg in regs(instr.operands()) {
+				if !defs[index].contains(&reg) {
+					uses[index].insert(reg);
+				}
+			}
+			defs[index].insert(instr.dest());
+		}
+		for reg in regs(block.terminator.operand().into_iter().collect()) {
+			if !defs[index].contains(&reg) {
+				uses[index].insert(reg);
+			}
+		}
+	}
 
-		Ok(())
+	let mut live_in: Vec<HashSet<VReg>> = vec![HashSet::new(); blocks.len()];
+	let mut live_out: Vec<HashSet<VReg>> = vec![HashSet::new(); blocks.len()];
+	let mut changed = true;
+	while changed {
+		changed = false;
+		for index in (0..blocks.len()).rev() {
+			let out: HashSet<VReg> = blocks[index].terminator.successors().iter()
+				.flat_map(|successor| live_in[successor.0].iter().copied())
+				.collect();
+			let mut live: HashSet<VReg> = out.difference(&defs[index]).copied().collect();
+			live.extend(&uses[index]);
+			if live != live_in[index] || out != live_out[index] {
+				live_in[index] = live;
+				live_out[index] = out;
+				changed = true;
+			}
+		}
 	}
 
-	/// Generates a loop from nodes
-	/// 
-	/// Unusual format
-	/// 
-	/// - while
-	/// - expr
-	/// - block
-	/// 
-	fn generate_loop(&mut self) -> Result<(), GeneratorError> {
-		match self.input.next().ok_or(GeneratorError::EndOfInput)?.variant {
-			NodeType::StmtWhile => (),
-			node_type => return Err(GeneratorError::UnexpectedNode(node_type))
-		};
+	let mut intervals: Vec<Option<Interval>> = vec![None; function.reg_count];
+	let mut extend = |reg: VReg, position: usize| {
+		let interval = intervals[reg.0].get_or_insert(Interval { reg, start: position, end: position });
+		interval.start = interval.start.min(position);
+		interval.end = interval.end.max(position);
+	};
+	let mut block_starts = Vec::with_capacity(blocks.len());
+	let mut position = 0;
+	for (index, block) in blocks.iter().enumerate() {
+		block_starts.push(position);
+		for reg in &live_in[index] {
+			extend(*reg, position);
+		}
+		for instr in &block.instrs {
+			for reg in regs(instr.operands()) {
+				extend(reg, position);
+			}
+			extend(instr.dest(), position + 1);
+			position += 2;
+		}
+		for reg in regs(block.terminator.operand().into_iter().collect()) {
+			extend(reg, position);
+		}
+		for reg in &live_out[index] {
+			extend(*reg, position + 1);
+		}
+		position += 2;
+	}
 
-		let start = self.create_label("loopstart");
-		let end = self.create_label("loopend");
+	(intervals, block_starts)
+}
 
-		self.output += &format!("{}:\n", start);
-		self.generate_conditional_jump(&end)?;
+/// Assigns each interval a register by linear scan, spilling the one that ends last when they run out
+///
+/// Returns the location of each virtual register and the number of spill slots used
+fn allocate_registers(intervals: &[Option<Interval>]) -> (Vec<Option<Location>>, usize) {
+	let mut sorted: Vec<Interval> = intervals.iter().flatten().copied().collect();
+	sorted.sort_by_key(|interval| (interval.start, interval.reg));
+
+	let mut locations = vec![None; intervals.len()];
+	let mut free: Vec<&'static str> = ALLOCATABLE_REGISTERS.iter().rev().copied().collect();
+	let mut active: Vec<(Interval, &'static str)> = Vec::new();
+	let mut slots = 0;
+
+	for interval in sorted {
+		active.retain(|(active, reg)| {
+			let expired = active.end < interval.start;
+			if expired {
+				free.push(reg);
+			}
+			!expired
+		});
 
-		self.generate_block()?;
+		if let Some(reg) = free.pop() {
+			locations[interval.reg.0] = Some(Location::Register(reg));
+			active.push((interval, reg));
+			continue;
+		}
 
-		self.output += &format!("jmp {}\n", start);
-		self.output += &format!("{}:\n", end);
-		
-		Ok(())
+		let (index, &(furthest, reg)) = active.iter().enumerate()
+			.max_by_key(|(_, (active, _))| active.end)
+			.expect("Registers only run out while some are active");
+		if furthest.end > interval.end {
+			locations[furthest.reg.0] = Some(Location::Stack(slots));
+			locations[interval.reg.0] = Some(Location::Register(reg));
+			active[index] = (interval, reg);
+		} else {
+			locations[interval.reg.0] = Some(Location::Stack(slots));
+		}
+		slots += 1;
 	}
-}
\ No newline at end of file
+
+	(locations, slots)
+}
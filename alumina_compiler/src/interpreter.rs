@@ -0,0 +1,231 @@
+use std::{mem, thread};
+
+use crate::parser::{Ast, BinaryOp, Expr, NodeId, NodeKind, NodeType, Stmt, UnaryOp};
+use crate::token::Span;
+
+/// Deepest call nesting before the program is stopped
+const MAX_CALL_DEPTH: usize = 10_000;
+/// Stack size of the interpreter thread, large enough for the deepest call nesting
+const STACK_SIZE: usize = 512 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum InterpreterError {
+	/// The program called `exit`, not an error
+	Exit(i64),
+	/// A function returned, not an error
+	Return(i64),
+	DivisionByZero(Span),
+	/// Dividing the smallest integer by -1
+	DivisionOverflow(Span),
+	StackOverflow(Span),
+	UnexpectedNode(NodeKind, Span)
+}
+
+/// Executes parsed programs directly, without generating assembly
+///
+/// Programs must already have been accepted by the [`Lowerer`](crate::lowering::Lowerer), which
+/// checks names, calls and returns, so only errors that depend on the values computed are reported
+/// here. Arithmetic wraps at 64 bits to match the native code
+pub struct Interpreter<'a> {
+	ast: &'a Ast,
+	variables: Vec<(String, i64)>,
+	/// Declared functions, their parameters and their body
+	functions: Vec<(String, Vec<String>, NodeId)>,
+	call_depth: usize
+}
+
+impl <'a> Interpreter<'a> {
+	/// Runs a program, returning its exit code as the operating system would report it
+	/// 
+	/// Calls are evaluated recursively, so the program runs on its own thread with a large stack
+	pub fn run(ast: &'a Ast) -> Result<u8, InterpreterError> {
+		thread::scope(|scope| {
+			thread::Builder::new()
+				.name(String::from("interpreter"))
+				.stack_size(STACK_SIZE)
+				.spawn_scoped(scope, || Interpreter::run_on_current_thread(ast))
+				.expect("Unable to start interpreter thread")
+				.join()
+				.unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+		})
+	}
+
+	fn run_on_current_thread(ast: &'a Ast) -> Result<u8, InterpreterError> {
+
+		let mut interpreter = Interpreter {
+			ast,
+			variables: Vec::new(),
+			functions: Vec::new(),
+			call_depth: 0
+		};
+
+		// Top level statements share the program's scope
+		let statements = match ast.variant(ast.root) {
+			NodeType::Block(statements) => statements,
+			_ => return Err(interpreter.unexpected(ast.root))
+		};
+		for statement in statements {
+			match interpreter.execute_statement(statement) {
+				Ok(_) => (),
+				// Only the lowest byte of the status reaches the parent process
+				Err(InterpreterError::Exit(code)) => return Ok(code as u8),
+				Err(InterpreterError::Return(_)) => unreachable!("Lowering rejects returns outside functions"),
+				Err(err) => return Err(err),
+			}
+		}
+
+		Ok(0)
+	}
+
+	/// Lowering checks that variables are declared before they are used
+	fn variable(&mut self, name: &str) -> &mut (String, i64) {
+		match self.variables.iter_mut().find(|(str, _)| str == name) {
+			Some(variable) => variable,
+			None => panic!("Variable `{}` was never declared", name)
+		}
+	}
+
+	fn unexpected(&self, id: NodeId) -> InterpreterError {
+		let node = self.ast.get(id);
+		InterpreterError::UnexpectedNode(node.kind.clone(), node.span)
+	}
+
+	fn execute_statement(&mut self, id: NodeId) -> Result<(), InterpreterError> {
+		let stmt = match self.ast.variant(id) {
+			NodeType::Block(_) => return self.execute_block(id),
+			NodeType::Stmt(stmt) => stmt,
+			NodeType::Expr(_) => return Err(self.unexpected(id))
+		};
+
+		match stmt {
+			Stmt::Exit(value) => {
+				let value = self.evaluate_expr(value)?;
+				return Err(InterpreterError::Exit(value));
+			},
+			Stmt::Let(name, value) => {
+				let value = self.evaluate_expr(value)?;
+				self.variables.push((name.to_string(), value));
+			},
+			Stmt::Assign(name, value) => {
+				let value = self.evaluate_expr(value)?;
+				self.variable(name).1 = value;
+			},
+			Stmt::If { condition, then_block, else_block } => {
+				if self.evaluate_expr(condition)? != 0 {
+					self.execute_block(then_block)?;
+				} else if let Some(else_block) = else_block {
+					self.execute_block(else_block)?;
+				}
+			},
+			Stmt::While { condition, body } => {
+				while self.evaluate_expr(condition)? != 0 {
+					self.execute_block(body)?;
+				}
+			},
+			Stmt::Function { name, params, body } => {
+				self.functions.push((name.to_string(), params.to_vec(), body));
+			},
+			Stmt::Return(value) => {
+				let value = self.evaluate_expr(value)?;
+				return Err(InterpreterError::Return(value));
+			},
+			Stmt::Expr(value) => {
+				self.evaluate_expr(value)?;
+			},
+		}
+		Ok(())
+	}
+
+	fn execute_block(&mut self, id: NodeId) -> Result<(), InterpreterError> {
+		let statements = match self.ast.variant(id) {
+			NodeType::Block(statements) => statements,
+			_ => return Err(self.unexpected(id))
+		};
+
+		let block_start = self.variables.len();
+		for statement in statements {
+			self.execute_statement(statement)?;
+		}
+		self.variables.truncate(block_start);
+
+		Ok(())
+	}
+
+	fn evaluate_expr(&mut self, id: NodeId) -> Result<i64, InterpreterError> {
+		let span = self.ast.get(id).span;
+		let expr = match self.ast.variant(id) {
+			NodeType::Expr(expr) => expr,
+			_ => return Err(self.unexpected(id))
+		};
+
+		Ok(match expr {
+			Expr::Ident(name) => {
+				self.variable(name).1
+			},
+			Expr::Literal(value) => value,
+			Expr::Call(name, arguments) => {
+				let arguments = arguments.into_iter()
+					.map(|argument| self.evaluate_expr(argument))
+					.collect::<Result<Vec<i64>, InterpreterError>>()?;
+				self.call(name, arguments, span)?
+			},
+			Expr::Unary(UnaryOp::Neg, operand) => self.evaluate_expr(operand)?.wrapping_neg(),
+			Expr::Unary(UnaryOp::Not, operand) => (self.evaluate_expr(operand)? == 0) as i64,
+			// The right operand is only evaluated when the left doesn't decide the result
+			Expr::Binary(BinaryOp::And, lhs, rhs) => (self.evaluate_expr(lhs)? != 0 && self.evaluate_expr(rhs)? != 0) as i64,
+			Expr::Binary(BinaryOp::Or, lhs, rhs) => (self.evaluate_expr(lhs)? != 0 || self.evaluate_expr(rhs)? != 0) as i64,
+			Expr::Binary(op, lhs, rhs) => {
+				let lhs = self.evaluate_expr(lhs)?;
+				let rhs = self.evaluate_expr(rhs)?;
+				evaluate_binary(op, lhs, rhs, span)?
+			},
+		})
+	}
+
+	fn call(&mut self, name: &str, arguments: Vec<i64>, span: Span) -> Result<i64, InterpreterError> {
+		let (params, body) = match self.functions.iter().find(|(str, _, _)| str == name) {
+			Some((_, params, body)) => (params.clone(), *body),
+			None => unreachable!("Lowering checks that functions are declared before they are called")
+		};
+		if self.call_depth >= MAX_CALL_DEPTH {
+			return Err(InterpreterError::StackOverflow(span));
+		}
+
+		// Functions only see their own parameters and locals
+		let outer_variables = mem::replace(&mut self.variables, params.into_iter().zip(arguments).collect());
+		self.call_depth += 1;
+
+		let value = match self.execute_block(body) {
+			// Functions without a return statement return 0
+			Ok(()) => 0,
+			Err(InterpreterError::Return(value)) => value,
+			Err(err) => return Err(err)
+		};
+
+		self.call_depth -= 1;
+		self.variables = outer_variables;
+
+		Ok(value)
+	}
+}
+
+/// Evaluates a binary operator with the same results as the generated assembly
+fn evaluate_binary(op: BinaryOp, lhs: i64, rhs: i64, span: Span) -> Result<i64, InterpreterError> {
+	Ok(match op {
+		BinaryOp::Add => lhs.wrapping_add(rhs),
+		BinaryOp::Sub => lhs.wrapping_sub(rhs),
+		BinaryOp::Mul => lhs.wrapping_mul(rhs),
+		BinaryOp::Div => match rhs {
+			0 => return Err(InterpreterError::DivisionByZero(span)),
+			_ => lhs.checked_div(rhs).ok_or(InterpreterError::DivisionOverflow(span))?
+		},
+		BinaryOp::Equal => (lhs == rhs) as i64,
+		BinaryOp::NotEqual => (lhs != rhs) as i64,
+		BinaryOp::Greater => (lhs > rhs) as i64,
+		BinaryOp::GreaterEqual => (lhs >= rhs) as i64,
+		BinaryOp::Less => (lhs < rhs) as i64,
+		BinaryOp::LessEqual => (lhs <= rhs) as i64,
+		BinaryOp::And => (lhs != 0 && rhs != 0) as i64,
+		BinaryOp::Or => (lhs != 0 || rhs != 0) as i64,
+	})
+}
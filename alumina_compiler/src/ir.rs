@@ -0,0 +1,358 @@
+//! Three-address intermediate representation, between the parse tree and assembly
+//!
+//! Each function is a list of basic blocks over any number of virtual registers.
+//! Blocks run their instructions in order and end in a single terminator, which
+//! is the only way control moves between them.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+
+use crate::parser::{BinaryOp, UnaryOp};
+
+/// Virtual register, numbered from 0 within each function
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct VReg(pub usize);
+
+/// Index of a basic block within its function
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockId(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operand {
+	Reg(VReg),
+	Const(i64)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instr {
+	Copy { dest: VReg, src: Operand },
+	Unary { dest: VReg, op: UnaryOp, operand: Operand },
+	/// Arithmetic or comparison, short circuiting operators are lowered to branches
+	Binary { dest: VReg, op: BinaryOp, lhs: Operand, rhs: Operand },
+	Call { dest: VReg, function: String, arguments: Vec<Operand> },
+	/// Value from whichever predecessor control came from, only at the start of blocks in SSA form
+	Phi { dest: VReg, incoming: Vec<(BlockId, Operand)> }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Terminator {
+	Jump(BlockId),
+	/// Goes to `then` if the condition is non-zero, otherwise to `otherwise`
+	Branch { condition: Operand, then: BlockId, otherwise: BlockId },
+	Return(Operand),
+	/// Ends the program with the value as its exit code
+	Exit(Operand)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+	pub instrs: Vec<Instr>,
+	pub terminator: Terminator
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+	pub name: String,
+	/// Registers holding the arguments on entry
+	pub params: Vec<VReg>,
+	/// The first block is the entry
+	pub blocks: Vec<Block>,
+	pub reg_count: usize
+}
+
+/// The top level statements, run from `_start`, and the functions they may call
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+	pub main: Function,
+	pub functions: Vec<Function>
+}
+
+impl Operand {
+	pub fn reg(self) -> Option<VReg> {
+		match self {
+			Operand::Reg(reg) => Some(reg),
+			Operand::Const(_) => None
+		}
+	}
+}
+
+impl Instr {
+	pub fn dest(&self) -> VReg {
+		match self {
+			Instr::Copy { dest, .. } | Instr::Unary { dest, .. } | Instr::Binary { dest, .. } |
+			Instr::Call { dest, .. } | Instr::Phi { dest, .. } => *dest
+		}
+	}
+
+	pub fn dest_mut(&mut self) -> &mut VReg {
+		match self {
+			Instr::Copy { dest, .. } | Instr::Unary { dest, .. } | Instr::Binary { dest, .. } |
+			Instr::Call { dest, .. } | Instr::Phi { dest, .. } => dest
+		}
+	}
+
+	/// Operands read by the instruction, in order
+	pub fn operands(&self) -> Vec<Operand> {
+		match self {
+			Instr::Copy { src, .. } => vec![*src],
+			Instr::Unary { operand, .. } => vec![*operand],
+			Instr::Binary { lhs, rhs, .. } => vec![*lhs, *rhs],
+			Instr::Call { arguments, .. } => arguments.clone(),
+			Instr::Phi { incoming, .. } => incoming.iter().map(|(_, operand)| *operand).collect()
+		}
+	}
+
+	pub fn operands_mut(&mut self) -> Vec<&mut Operand> {
+		match self {
+			Instr::Copy { src, .. } => vec![src],
+			Instr::Unary { operand, .. } => vec![operand],
+			Instr::Binary { lhs, rhs, .. } => vec![lhs, rhs],
+			Instr::Call { arguments, .. } => arguments.iter_mut().collect(),
+			Instr::Phi { incoming, .. } => incoming.iter_mut().map(|(_, operand)| operand).collect()
+		}
+	}
+
+	/// Whether removing the instruction, or running it more or less often, can change what the program does
+	///
+	/// Calls may exit or never return, and division traps on zero or overflow
+	pub fn has_side_effects(&self) -> bool {
+		match self {
+			Instr::Call { .. } => true,
+			Instr::Binary { op: BinaryOp::Div, rhs, .. } => !matches!(rhs, Operand::Const(value) if *value != 0 && *value != -1),
+			_ => false
+		}
+	}
+}
+
+impl Terminator {
+	pub fn operand(&self) -> Option<Operand> {
+		match self {
+			Terminator::Jump(_) => None,
+			Terminator::Branch { condition, .. } => Some(*condition),
+			Terminator::Return(value) | Terminator::Exit(value) => Some(*value)
+		}
+	}
+
+	pub fn operand_mut(&mut self) -> Option<&mut Operand> {
+		match self {
+			Terminator::Jump(_) => None,
+			Terminator::Branch { condition, .. } => Some(condition),
+			Terminator::Return(value) | Terminator::Exit(value) => Some(value)
+		}
+	}
+
+	/// Blocks that control may move to next
+	pub fn successors(&self) -> Vec<BlockId> {
+		match self {
+			Terminator::Jump(block) => vec![*block],
+			Terminator::Branch { then, otherwise, .. } => vec![*then, *otherwise],
+			Terminator::Return(_) | Terminator::Exit(_) => Vec::new()
+		}
+	}
+
+	pub fn successors_mut(&mut self) -> Vec<&mut BlockId> {
+		match self {
+			Terminator::Jump(block) => vec![block],
+			Terminator::Branch { then, otherwise, .. } => vec![then, otherwise],
+			Terminator::Return(_) | Terminator::Exit(_) => Vec::new()
+		}
+	}
+}
+
+impl Block {
+	/// The phis at the start of the block
+	pub fn phis(&self) -> impl Iterator<Item = &Instr> {
+		self.instrs.iter().take_while(|instr| matches!(instr, Instr::Phi { .. }))
+	}
+}
+
+impl Function {
+	pub fn new_reg(&mut self) -> VReg {
+		self.reg_count += 1;
+		VReg(self.reg_count - 1)
+	}
+
+	/// Predecessors of each block, in the order of their blocks
+	pub fn predecessors(&self) -> Vec<Vec<BlockId>> {
+		let mut predecessors = vec![Vec::new(); self.blocks.len()];
+		for (index, block) in self.blocks.iter().enumerate() {
+			for successor in block.terminator.successors() {
+				predecessors[successor.0].push(BlockId(index));
+			}
+		}
+		predecessors
+	}
+
+	/// Rewrites every operand reading a replaced register, following chains of replacements
+	pub fn replace_uses(&mut self, replacements: &HashMap<VReg, Operand>) {
+		let resolve = |mut operand: Operand| {
+			while let Operand::Reg(reg) = operand {
+				match replacements.get(&reg) {
+					Some(replacement) => operand = *replacement,
+					None => break
+				}
+			}
+			operand
+		};
+		for block in &mut self.blocks {
+			for instr in &mut block.instrs {
+				for operand in instr.operands_mut() {
+					*operand = resolve(*operand);
+				}
+			}
+			if let Some(operand) = block.terminator.operand_mut() {
+				*operand = resolve(*operand);
+			}
+		}
+	}
+
+	/// Inserts a block at an index, moving later blocks along, and returns its id
+	///
+	/// The new block refers to other blocks by their ids from before the insertion
+	pub fn insert_block(&mut self, index: usize, block: Block) -> BlockId {
+		self.blocks.insert(index, block);
+		self.renumber_blocks(|id| if id.0 >= index { BlockId(id.0 + 1) } else { id });
+		BlockId(index)
+	}
+
+	/// Drops blocks that can't be reached from the entry, and phi values coming from them
+	///
+	/// Returns whether any blocks were dropped
+	pub fn remove_unreachable_blocks(&mut self) -> bool {
+		let mut reachable = vec![false; self.blocks.len()];
+		let mut stack = vec![BlockId(0)];
+		while let Some(block) = stack.pop() {
+			if !mem::replace(&mut reachable[block.0], true) {
+				stack.extend(self.blocks[block.0].terminator.successors());
+			}
+		}
+		if reachable.iter().all(|reachable| *reachable) {
+			return false;
+		}
+
+		let mut renumbered = Vec::with_capacity(self.blocks.len());
+		let mut count = 0;
+		for &reachable in &reachable {
+			renumbered.push(BlockId(count));
+			count += reachable as usize;
+		}
+		let mut index = 0;
+		self.blocks.retain(|_| {
+			index += 1;
+			reachable[index - 1]
+		});
+		for block in &mut self.blocks {
+			for instr in &mut block.instrs {
+				if let Instr::Phi { incoming, .. } = instr {
+					incoming.retain(|(block, _)| reachable[block.0]);
+				}
+			}
+		}
+		self.renumber_blocks(|id| renumbered[id.0]);
+		true
+	}
+
+	fn renumber_blocks(&mut self, renumber: impl Fn(BlockId) -> BlockId) {
+		for block in &mut self.blocks {
+			for successor in block.terminator.successors_mut() {
+				*successor = renumber(*successor);
+			}
+			for instr in &mut block.instrs {
+				if let Instr::Phi { incoming, .. } = instr {
+					for (block, _) in incoming {
+						*block = renumber(*block);
+					}
+				}
+			}
+		}
+	}
+}
+
+impl fmt::Display for VReg {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "%{}", self.0)
+	}
+}
+impl fmt::Display for BlockId {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "bb{}", self.0)
+	}
+}
+impl fmt::Display for Operand {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Operand::Reg(reg) => write!(f, "{reg}"),
+			Operand::Const(value) => write!(f, "{value}")
+		}
+	}
+}
+
+fn binary_name(op: BinaryOp) -> &'static str {
+	match op {
+		BinaryOp::Add => "add",
+		BinaryOp::Sub => "sub",
+		BinaryOp::Mul => "mul",
+		BinaryOp::Div => "div",
+		BinaryOp::Equal => "eq",
+		BinaryOp::NotEqual => "ne",
+		BinaryOp::Greater => "gt",
+		BinaryOp::GreaterEqual => "ge",
+		BinaryOp::Less => "lt",
+		BinaryOp::LessEqual => "le",
+		BinaryOp::And => "and",
+		BinaryOp::Or => "or"
+	}
+}
+
+impl fmt::Display for Instr {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Instr::Copy { dest, src } => write!(f, "{dest} = {src}"),
+			Instr::Unary { dest, op: UnaryOp::Neg, operand } => write!(f, "{dest} = neg {operand}"),
+			Instr::Unary { dest, op: UnaryOp::Not, operand } => write!(f, "{dest} = not {operand}"),
+			Instr::Binary { dest, op, lhs, rhs } => write!(f, "{dest} = {} {lhs}, {rhs}", binary_name(*op)),
+			Instr::Call { dest, function, arguments } => {
+				let arguments: Vec<String> = arguments.iter().map(Operand::to_string).collect();
+				write!(f, "{dest} = call {function}({})", arguments.join(", "))
+			},
+			Instr::Phi { dest, incoming } => {
+				let incoming: Vec<String> = incoming.iter().map(|(block, operand)| format!("[{block}: {operand}]")).collect();
+				write!(f, "{dest} = phi {}", incoming.join(", "))
+			}
+		}
+	}
+}
+impl fmt::Display for Terminator {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Terminator::Jump(block) => write!(f, "jump {block}"),
+			Terminator::Branch { condition, then, otherwise } => write!(f, "branch {condition}, {then}, {otherwise}"),
+			Terminator::Return(value) => write!(f, "return {value}"),
+			Terminator::Exit(value) => write!(f, "exit {value}")
+		}
+	}
+}
+impl fmt::Display for Function {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let params: Vec<String> = self.params.iter().map(VReg::to_string).collect();
+		writeln!(f, "fn {}({}) {{", self.name, params.join(", "))?;
+		for (index, block) in self.blocks.iter().enumerate() {
+			writeln!(f, "{}:", BlockId(index))?;
+			for instr in &block.instrs {
+				writeln!(f, "    {instr}")?;
+			}
+			writeln!(f, "    {}", block.terminator)?;
+		}
+		writeln!(f, "}}")
+	}
+}
+impl fmt::Display for Program {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", self.main)?;
+		for function in &self.functions {
+			write!(f, "\n{function}")?;
+		}
+		Ok(())
+	}
+}
@@ -0,0 +1,15 @@
+extern crate char_reader;
+extern crate flat_tree;
+
+pub mod token;
+pub mod parser;
+pub mod folding;
+pub mod ir;
+pub mod lowering;
+pub mod ssa;
+pub mod optimisation;
+pub mod asm;
+pub mod generation;
+pub mod peephole;
+pub mod diagnostics;
+pub mod interpreter;
@@ -0,0 +1,391 @@
+use std::mem;
+
+use crate::generation::{GeneratorError, ARGUMENT_REGISTERS};
+use crate::ir::{Block, BlockId, Function, Instr, Operand, Program, Terminator, VReg};
+use crate::parser::{Ast, BinaryOp, Expr, NodeId, NodeType, Stmt};
+use crate::token::Span;
+
+/// Function whose blocks are still being added to
+struct FunctionBuilder {
+	name: String,
+	params: Vec<VReg>,
+	/// Blocks without a terminator are still open
+	blocks: Vec<(Vec<Instr>, Option<Terminator>)>,
+	/// Block that instructions are added to
+	current: BlockId,
+	reg_count: usize,
+	/// Terminator for blocks that fall off the end of the function
+	fallthrough: Terminator
+}
+
+impl FunctionBuilder {
+	fn new(name: &str, fallthrough: Terminator) -> FunctionBuilder {
+		FunctionBuilder {
+			name: name.to_string(),
+			params: Vec::new(),
+			blocks: vec![(Vec::new(), None)],
+			current: BlockId(0),
+			reg_count: 0,
+			fallthrough
+		}
+	}
+
+	fn new_reg(&mut self) -> VReg {
+		self.reg_count += 1;
+		VReg(self.reg_count - 1)
+	}
+
+	fn new_block(&mut self) -> BlockId {
+		self.blocks.push((Vec::new(), None));
+		BlockId(self.blocks.len() - 1)
+	}
+
+	fn switch_to(&mut self, block: BlockId) {
+		self.current = block;
+	}
+
+	fn is_terminated(&self) -> bool {
+		self.blocks[self.current.0].1.is_some()
+	}
+
+	/// Adds an instruction to the current block
+	///
+	/// Code after a terminator can't be reached, so it goes into a new block without predecessors
+	fn push(&mut self, instr: Instr) {
+		if self.is_terminated() {
+			let block = self.new_block();
+			self.switch_to(block);
+		}
+		self.blocks[self.current.0].0.push(instr);
+	}
+
+	/// Last instruction of the current block, if it is still open
+	fn last_instr(&mut self) -> Option<&mut Instr> {
+		match &mut self.blocks[self.current.0] {
+			(instrs, None) => instrs.last_mut(),
+			(_, Some(_)) => None
+		}
+	}
+
+	/// Ends the current block, unless it has already ended
+	fn terminate(&mut self, terminator: Terminator) {
+		if !self.is_terminated() {
+			self.blocks[self.current.0].1 = Some(terminator);
+		}
+	}
+
+	/// Block to start a loop at, the current one if nothing has been added to it
+	fn loop_header(&mut self) -> BlockId {
+		let (instrs, terminator) = &self.blocks[self.current.0];
+		if instrs.is_empty() && terminator.is_none() {
+			return self.current;
+		}
+		let header = self.new_block();
+		self.terminate(Terminator::Jump(header));
+		self.switch_to(header);
+		header
+	}
+
+	/// Closes any open blocks and drops those that can't be reached
+	fn finish(self) -> Function {
+		let fallthrough = self.fallthrough;
+		let blocks = self.blocks.into_iter()
+			.map(|(instrs, terminator)| Block { instrs, terminator: terminator.unwrap_or_else(|| fallthrough.clone()) })
+			.collect();
+		let mut function = Function { name: self.name, params: self.params, blocks, reg_count: self.reg_count };
+		function.remove_unreachable_blocks();
+		function
+	}
+}
+
+/// Lowers a parsed program into the intermediate representation, checking names and calls as it goes
+pub struct Lowerer<'a> {
+	ast: &'a Ast,
+	/// Variables in scope and the registers holding them
+	variables: Vec<(String, VReg)>,
+	/// Declared functions and their parameter counts
+	functions: Vec<(String, usize)>,
+	/// Whether a function body is being lowered
+	in_function: bool,
+	scopes: Vec<usize>,
+	function: FunctionBuilder,
+	lowered: Vec<Function>
+}
+
+impl <'a> Lowerer<'a> {
+	pub fn lower_program(ast: &'a Ast) -> Result<Program, GeneratorError> {
+		let mut lowerer = Lowerer {
+			ast,
+			variables: Vec::new(),
+			functions: Vec::new(),
+			in_function: false,
+			scopes: Vec::new(),
+			function: FunctionBuilder::new("_start", Terminator::Exit(Operand::Const(0))),
+			lowered: Vec::new()
+		};
+
+		// Top level statements share the program's scope
+		let statements = match ast.variant(ast.root) {
+			NodeType::Block(statements) => statements,
+			_ => return Err(lowerer.unexpected(ast.root))
+		};
+		for statement in statements {
+			lowerer.lower_statement(statement)?;
+		}
+
+		Ok(Program { main: lowerer.function.finish(), functions: lowerer.lowered })
+	}
+
+	fn unexpected(&self, id: NodeId) -> GeneratorError {
+		let node = self.ast.get(id);
+		GeneratorError::UnexpectedNode(node.kind.clone(), node.span)
+	}
+
+	fn variable(&self, name: &str, span: Span) -> Result<VReg, GeneratorError> {
+		match self.variables.iter().find(|(str, _)| str == name) {
+			Some(var) => Ok(var.1),
+			None => Err(GeneratorError::VariableNotYetDeclared(name.to_string(), span))
+		}
+	}
+
+	/// Whether a register holds an intermediate value rather than a variable
+	fn is_temporary(&self, reg: VReg) -> bool {
+		!self.variables.iter().any(|(_, var)| *var == reg)
+	}
+
+	fn lower_statement(&mut self, id: NodeId) -> Result<(), GeneratorError> {
+		let span = self.ast.get(id).span;
+		match self.ast.variant(id) {
+			NodeType::Block(_) => self.lower_block(id)?,
+			NodeType::Stmt(Stmt::Exit(value)) => {
+				let value = self.lower_expr(value)?;
+				self.function.terminate(Terminator::Exit(value));
+			},
+			NodeType::Stmt(Stmt::If { condition, then_block, else_block }) => self.lower_conditional(condition, then_block, else_block)?,
+			NodeType::Stmt(Stmt::While { condition, body }) => self.lower_loop(condition, body)?,
+			NodeType::Stmt(Stmt::Let(name, value)) => self.lower_variable(name, value, span)?,
+			NodeType::Stmt(Stmt::Assign(name, value)) => self.lower_assignment(name, value, span)?,
+			NodeType::Stmt(Stmt::Function { name, params, body }) => self.lower_function_definition(name, params, body, span)?,
+			NodeType::Stmt(Stmt::Return(value)) => {
+				if !self.in_function {
+					return Err(GeneratorError::ReturnOutsideFunction(span));
+				}
+				let value = self.lower_expr(value)?;
+				self.function.terminate(Terminator::Return(value));
+			},
+			NodeType::Stmt(Stmt::Expr(value)) => {
+				self.lower_expr(value)?;
+			},
+			NodeType::Expr(_) => return Err(self.unexpected(id))
+		}
+		Ok(())
+	}
+
+	fn lower_block(&mut self, id: NodeId) -> Result<(), GeneratorError> {
+		let statements = match self.ast.variant(id) {
+			NodeType::Block(statements) => statements,
+			_ => return Err(self.unexpected(id))
+		};
+
+		self.scopes.push(self.variables.len());
+
+		for statement in statements {
+			self.lower_statement(statement)?;
+		}
+
+		let block_start = self.scopes.pop()
+			.ok_or(GeneratorError::BlockNotYetOpened)?;
+		self.variables.truncate(block_start);
+
+		Ok(())
+	}
+
+	fn lower_variable(&mut self, name: &str, value: NodeId, span: Span) -> Result<(), GeneratorError> {
+		if self.variables.iter().any(|(str, _)| str == name) {
+			return Err(GeneratorError::VariableAlreadyDeclared(name.to_string(), span));
+		}
+
+		// A new value can become the variable, but another variable has to be copied
+		let reg = match self.lower_expr(value)? {
+			Operand::Reg(reg) if self.is_temporary(reg) => reg,
+			value => {
+				let reg = self.function.new_reg();
+				self.function.push(Instr::Copy { dest: reg, src: value });
+				reg
+			}
+		};
+		self.variables.push((name.to_owned(), reg));
+
+		Ok(())
+	}
+
+	fn lower_assignment(&mut self, name: &str, value: NodeId, span: Span) -> Result<(), GeneratorError> {
+		let var = self.variable(name, span)?;
+		let value = self.lower_expr(value)?;
+
+		// Write straight into the variable when the value was only just computed
+		if let Operand::Reg(reg) = value {
+			let temporary = self.is_temporary(reg);
+			if let Some(instr) = self.function.last_instr().filter(|instr| temporary && instr.dest() == reg) {
+				*instr.dest_mut() = var;
+				return Ok(());
+			}
+		}
+		self.function.push(Instr::Copy { dest: var, src: value });
+
+		Ok(())
+	}
+
+	/// Lowers an expression, returning the operand that holds its value
+	fn lower_expr(&mut self, id: NodeId) -> Result<Operand, GeneratorError> {
+		let span = self.ast.get(id).span;
+		let expr = match self.ast.variant(id) {
+			NodeType::Expr(expr) => expr,
+			_ => return Err(self.unexpected(id))
+		};
+
+		let instr = match expr {
+			Expr::Ident(name) => return Ok(Operand::Reg(self.variable(name, span)?)),
+			Expr::Literal(num) => return Ok(Operand::Const(num)),
+			Expr::Call(name, arguments) => {
+				let params = match self.functions.iter().find(|(str, _)| str == name) {
+					Some(function) => function.1,
+					None => return Err(GeneratorError::FunctionNotYetDeclared(name.to_string(), span))
+				};
+				if params != arguments.len() {
+					return Err(GeneratorError::ArgumentCountMismatch(name.to_string(), params, arguments.len(), span));
+				}
+				let arguments = arguments.into_iter()
+					.map(|argument| self.lower_expr(argument))
+					.collect::<Result<Vec<_>, _>>()?;
+				Instr::Call { dest: self.function.new_reg(), function: name.to_string(), arguments }
+			},
+			Expr::Unary(op, operand) => {
+				let operand = self.lower_expr(operand)?;
+				Instr::Unary { dest: self.function.new_reg(), op, operand }
+			},
+			Expr::Binary(op @ (BinaryOp::And | BinaryOp::Or), lhs, rhs) => return self.lower_logical(op, lhs, rhs),
+			Expr::Binary(op, lhs, rhs) => {
+				let lhs = self.lower_expr(lhs)?;
+				let rhs = self.lower_expr(rhs)?;
+				Instr::Binary { dest: self.function.new_reg(), op, lhs, rhs }
+			}
+		};
+
+		let dest = instr.dest();
+		self.function.push(instr);
+		Ok(Operand::Reg(dest))
+	}
+
+	/// Lowers a short circuiting `&&` or `||` into branches
+	///
+	/// The value of the left operand decides whether the right operand is skipped,
+	/// the result is always normalised to 0 or 1
+	fn lower_logical(&mut self, op: BinaryOp, lhs: NodeId, rhs: NodeId) -> Result<Operand, GeneratorError> {
+		let lhs = self.lower_expr(lhs)?;
+		let dest = self.function.new_reg();
+		let evaluate = self.function.new_block();
+		let short_circuit = self.function.new_block();
+		let end = self.function.new_block();
+
+		self.function.terminate(match op {
+			BinaryOp::And => Terminator::Branch { condition: lhs, then: evaluate, otherwise: short_circuit },
+			_ => Terminator::Branch { condition: lhs, then: short_circuit, otherwise: evaluate },
+		});
+
+		self.function.switch_to(evaluate);
+		let rhs = self.lower_expr(rhs)?;
+		self.function.push(Instr::Binary { dest, op: BinaryOp::NotEqual, lhs: rhs, rhs: Operand::Const(0) });
+		self.function.terminate(Terminator::Jump(end));
+
+		self.function.switch_to(short_circuit);
+		let value = if op == BinaryOp::And { 0 } else { 1 };
+		self.function.push(Instr::Copy { dest, src: Operand::Const(value) });
+		self.function.terminate(Terminator::Jump(end));
+
+		self.function.switch_to(end);
+		Ok(Operand::Reg(dest))
+	}
+
+	/// Lowers a function definition, which can only call functions declared before it and itself
+	fn lower_function_definition(&mut self, name: &str, params: &[String], body: NodeId, span: Span) -> Result<(), GeneratorError> {
+		if self.in_function || !self.scopes.is_empty() {
+			return Err(GeneratorError::NestedFunction(name.to_string(), span));
+		}
+		if self.functions.iter().any(|(str, _)| str == name) {
+			return Err(GeneratorError::FunctionAlreadyDeclared(name.to_string(), span));
+		}
+		if params.len() > ARGUMENT_REGISTERS.len() {
+			return Err(GeneratorError::TooManyParameters(name.to_string(), span));
+		}
+		for (index, param) in params.iter().enumerate() {
+			if params[..index].contains(param) {
+				return Err(GeneratorError::VariableAlreadyDeclared(param.clone(), span));
+			}
+		}
+
+		// Declared before the body to allow recursion
+		self.functions.push((name.to_string(), params.len()));
+
+		// Functions without a return statement return 0
+		let outer_function = mem::replace(&mut self.function, FunctionBuilder::new(name, Terminator::Return(Operand::Const(0))));
+		let outer_variables = mem::take(&mut self.variables);
+		self.in_function = true;
+
+		for param in params {
+			let reg = self.function.new_reg();
+			self.function.params.push(reg);
+			self.variables.push((param.clone(), reg));
+		}
+		self.lower_block(body)?;
+
+		let function = mem::replace(&mut self.function, outer_function);
+		self.lowered.push(function.finish());
+		self.variables = outer_variables;
+		self.in_function = false;
+
+		Ok(())
+	}
+
+	fn lower_conditional(&mut self, condition: NodeId, then_block: NodeId, else_block: Option<NodeId>) -> Result<(), GeneratorError> {
+		let condition = self.lower_expr(condition)?;
+		let then = self.function.new_block();
+		let otherwise = self.function.new_block();
+		self.function.terminate(Terminator::Branch { condition, then, otherwise });
+
+		self.function.switch_to(then);
+		self.lower_block(then_block)?;
+
+		let Some(else_block) = else_block else {
+			self.function.terminate(Terminator::Jump(otherwise));
+			self.function.switch_to(otherwise);
+			return Ok(())
+		};
+		let end = self.function.new_block();
+		self.function.terminate(Terminator::Jump(end));
+
+		self.function.switch_to(otherwise);
+		self.lower_block(else_block)?;
+		self.function.terminate(Terminator::Jump(end));
+
+		self.function.switch_to(end);
+
+		Ok(())
+	}
+
+	fn lower_loop(&mut self, condition: NodeId, body: NodeId) -> Result<(), GeneratorError> {
+		let header = self.function.loop_header();
+		let condition = self.lower_expr(condition)?;
+		let start = self.function.new_block();
+		let end = self.function.new_block();
+		self.function.terminate(Terminator::Branch { condition, then: start, otherwise: end });
+
+		self.function.switch_to(start);
+		self.lower_block(body)?;
+		self.function.terminate(Terminator::Jump(header));
+
+		self.function.switch_to(end);
+
+		Ok(())
+	}
+}
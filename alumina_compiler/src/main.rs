@@ -1,108 +1,336 @@
 use std::{fs, process, env};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
-extern crate char_reader;
+extern crate alumina_compiler;
 
-pub(crate) mod token;
-pub(crate) mod parser;
-pub(crate) mod generation;
+mod cli;
 
-use token::Lexer;
-use parser::Parser;
-use generation::Generator;
+use alumina_compiler::{token, parser, folding, generation, interpreter};
+use alumina_compiler::token::{Lexer, SpannedToken};
+use alumina_compiler::parser::Parser;
+use alumina_compiler::folding::Folder;
+use alumina_compiler::generation::Generator;
+use alumina_compiler::lowering::Lowerer;
+use alumina_compiler::optimisation::{self, OptLevel};
+use alumina_compiler::{asm, peephole};
+use alumina_compiler::diagnostics::Diagnostic;
+use alumina_compiler::interpreter::Interpreter;
+use cli::{Command, Emit, Options};
 
+/// Name shown in diagnostics for source read from standard input
+const STDIN_NAME: &str = "<stdin>";
+
+/// Errors while processing an input, which may borrow tokens from its source
 #[derive(Debug)]
-enum CLIError {
+enum CLIError<'a> {
     IO(std::io::Error),
     Lexer(token::LexerError),
-    Parser(parser::ParserError),
-    CodeGenerator(generation::GeneratorError)
+    Parser(Vec<parser::ParserError<'a>>),
+    Folding(folding::FoldError),
+    CodeGenerator(generation::GeneratorError),
+    Interpreter(interpreter::InterpreterError),
+    /// An external tool could not be started
+    Tool(String, std::io::Error),
+    /// An external tool reported a failure
+    ToolFailed(String, process::ExitStatus),
+    /// The compiled program was stopped without an exit code
+    Terminated(process::ExitStatus)
 }
-impl From<std::io::Error> for CLIError {
+impl From<std::io::Error> for CLIError<'_> {
     fn from(value: std::io::Error) -> Self {
         CLIError::IO(value)
     }
 }
-impl From<token::LexerError> for CLIError {
+impl From<token::LexerError> for CLIError<'_> {
     fn from(value: token::LexerError) -> Self {
         CLIError::Lexer(value)
     }
 }
-impl From<parser::ParserError> for CLIError {
-    fn from(value: parser::ParserError) -> Self {
+impl<'a> From<Vec<parser::ParserError<'a>>> for CLIError<'a> {
+    fn from(value: Vec<parser::ParserError<'a>>) -> Self {
         CLIError::Parser(value)
     }
 }
-impl From<generation::GeneratorError> for CLIError {
+impl From<folding::FoldError> for CLIError<'_> {
+    fn from(value: folding::FoldError) -> Self {
+        CLIError::Folding(value)
+    }
+}
+impl From<generation::GeneratorError> for CLIError<'_> {
     fn from(value: generation::GeneratorError) -> Self { CLIError::CodeGenerator(value) }
 }
+impl From<interpreter::InterpreterError> for CLIError<'_> {
+    fn from(value: interpreter::InterpreterError) -> Self {
+        CLIError::Interpreter(value)
+    }
+}
+impl CLIError<'_> {
+    fn diagnostics(&self) -> Vec<Diagnostic> {
+        match self {
+            CLIError::IO(err) => vec![Diagnostic::error(format!("I/O error: {err}"))],
+            CLIError::Lexer(err) => vec![err.into()],
+            CLIError::Parser(errors) => errors.iter().map(Diagnostic::from).collect(),
+            CLIError::Folding(err) => vec![err.into()],
+            CLIError::CodeGenerator(err) => vec![err.into()],
+            CLIError::Interpreter(err) => vec![err.into()],
+            CLIError::Tool(tool, err) => vec![
+                Diagnostic::error(format!("failed to run `{tool}`: {err}"))
+                    .with_note(format!("`{tool}` must be installed to build native binaries, or use `run --interpret`"))
+            ],
+            CLIError::ToolFailed(tool, status) => vec![Diagnostic::error(format!("`{tool}` failed with {status}"))],
+            CLIError::Terminated(status) => vec![Diagnostic::error(format!("program terminated by {status}"))],
+        }
+    }
+}
+
+/// A source file and where its build artifacts are written
+struct Input {
+    /// Name shown in diagnostics
+    name: String,
+    /// File name used for artifacts in the build directory
+    stem: String,
+    source: String
+}
 
 
-fn main() -> Result<(), CLIError> {
-    let args: Vec<String> = env::args().collect();
-    if args.len() == 1 {
-        println!("Alumina compiler");
-        println!();
-        println!("Usage: alumina-compiler [FILE] [options]?");
-        println!("Options:");
-        println!("  -tokens");
-        println!("  -parse-tree");
-        return Ok(())
-    }
-    
-    println!(" \x1b[1;32m Compiling \x1b[0m '{}'...", &args[1]);
-    let file = fs::File::open(&args[1])?;
-
-    print!("   \x1b[1;34m Parsing \x1b[0m tokens...\r");
-    let lexer;
-    if args.iter().any(|i| i == "-tokens") {
-        println!("");
-        for token in Lexer::tokenize(file)? {
-            println!("{token:?}")
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match Options::parse(&args) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            print!("{}", cli::USAGE);
+            return ExitCode::SUCCESS
+        },
+        Err(message) => {
+            eprint!("{}", Diagnostic::error(message).render("", ""));
+            eprintln!("For more information, try '--help'.");
+            return ExitCode::from(2)
+        }
+    };
+
+    let mut result = ExitCode::SUCCESS;
+    // A failure is kept even if later files succeed
+    let mut failed = false;
+    for path in &options.inputs {
+        let input = match read_input(path) {
+            Ok(input) => input,
+            Err(err) => {
+                let diagnostic = Diagnostic::error(format!("couldn't read `{path}`: {err}"));
+                eprint!("{}", diagnostic.render(path, ""));
+                result = ExitCode::FAILURE;
+                failed = true;
+                continue
+            }
+        };
+
+        match process_input(&options, &input) {
+            Ok(_) if failed => (),
+            Ok(code) => result = code,
+            Err(err) => {
+                // Finish the in-progress status line
+                eprintln!();
+                let diagnostics = err.diagnostics();
+                for diagnostic in &diagnostics {
+                    eprintln!("{}", diagnostic.render(&input.name, &input.source));
+                }
+                if diagnostics.len() > 1 {
+                    eprintln!("\x1b[1;31merror\x1b[0m\x1b[1m: aborting due to {} previous errors\x1b[0m", diagnostics.len());
+                }
+                result = ExitCode::FAILURE;
+                failed = true;
+            }
         }
-        return Ok(());
-    } else {
-        lexer = Lexer::new(file);    
     }
+    result
+}
 
-    print!("  \x1b[1;34m Building \x1b[0m parse tree...\r");
-    let nodes = Parser::parse(lexer)?;
-    if args.iter().any(|i| i == "-parse-tree") {
-        println!("");
-        for (i, node) in nodes.iter().enumerate() {
-            println!("{i:<6} {node}");
+/// Reads a source file, or standard input for `-`
+fn read_input(path: &str) -> io::Result<Input> {
+    if path == "-" {
+        let mut source = String::new();
+        io::stdin().read_to_string(&mut source)?;
+        return Ok(Input { name: String::from(STDIN_NAME), stem: String::from("stdin"), source })
+    }
+
+    let stem = Path::new(path).file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| String::from("output"));
+    Ok(Input { name: path.to_string(), stem, source: fs::read_to_string(path)? })
+}
+
+/// Compiles a single input as far as the options ask, returning the exit code to report
+fn process_input<'a>(options: &Options, input: &'a Input) -> Result<ExitCode, CLIError<'a>> {
+    let name = &input.name;
+    eprintln!(" \x1b[1;32m Compiling \x1b[0m '{name}'...");
+
+    eprint!("   \x1b[1;34m Parsing \x1b[0m tokens...\r");
+    let tokens = Lexer::tokenize_str(&input.source)?;
+    if options.emit == Emit::Tokens {
+        let mut text = String::new();
+        for SpannedToken { token, span } in tokens {
+            text += &format!("{:<8} {token:?}\n", span.to_string());
         }
-        return Ok(())
+        write_text(options, &text)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    eprint!("  \x1b[1;34m Building \x1b[0m parse tree...\r");
+    let (mut ast, errors) = Parser::parse(tokens.into_iter());
+    match options.emit {
+        Emit::Ast => write_text(options, &ast.to_string())?,
+        Emit::AstDot => write_text(options, &ast.to_dot())?,
+        _ => (),
+    }
+    if !errors.is_empty() {
+        return Err(errors.into());
+    }
+    if matches!(options.emit, Emit::Ast | Emit::AstDot) {
+        return Ok(ExitCode::SUCCESS);
     }
 
-    print!("\x1b[1;34m Generating \x1b[0m intermediate code...\r");
-    let code = Generator::generate_program(nodes.into_iter())?;
+    eprint!("   \x1b[1;34m Folding \x1b[0m constants...\r");
+    let warnings = Folder::fold(&mut ast)?;
+    for warning in &warnings {
+        // Clear the in-progress status line
+        eprint!("\x1b[2K");
+        eprintln!("{}", Diagnostic::from(warning).render(&input.name, &input.source));
+    }
 
-    fs::create_dir_all("build")?;
-    fs::write("build/output.asm", code)?;
+    eprint!("\x1b[1;34m Generating \x1b[0m intermediate code...\r");
+    let mut program = Lowerer::lower_program(&ast)?;
+    // The interpreter only needs the checks lowering makes, not the native backend
+    if options.command == Command::Run && options.interpret {
+        eprintln!("   \x1b[1;32m Running \x1b[0m interpreter");
+        return Ok(ExitCode::from(Interpreter::run(&ast)?));
+    }
+    optimisation::optimise(&mut program, options.opt_level);
+    if options.emit == Emit::Ir {
+        write_text(options, &program.to_string())?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    let mut lines = Generator::generate_lines(&program);
+    if options.opt_level != OptLevel::O0 {
+        peephole::optimise(&mut lines);
+    }
+    let code = asm::render(&lines);
 
-    print!("  \x1b[1;34m Building \x1b[0m binary...\r");
+    match options.command {
+        Command::Check => {
+            eprintln!("  \x1b[1;32m Finished \x1b[0m checking '{name}' successfully");
+            Ok(ExitCode::SUCCESS)
+        },
+        Command::Run => {
+            let binary = build(options, input, &code)?;
+            execute(&binary)
+        },
+        Command::Build | Command::Emit if options.emit == Emit::Asm => {
+            write_text(options, &code)?;
+            Ok(ExitCode::SUCCESS)
+        },
+        Command::Build | Command::Emit => {
+            let artifact = build(options, input, &code)?;
+            eprintln!("  \x1b[1;32m Finished \x1b[0m compiling '{name}' into '{}'", artifact.display());
+            Ok(ExitCode::SUCCESS)
+        }
+    }
+}
+
+/// Writes a text form to the output path, or standard output if none was given
+fn write_text(options: &Options, text: &str) -> io::Result<()> {
+    match &options.output {
+        Some(path) => fs::write(path, text),
+        None => {
+            // Clear the in-progress status line
+            eprint!("\x1b[2K");
+            io::stdout().write_all(text.as_bytes())
+        }
+    }
+}
+
+/// Path of an artifact, `-o` if it is the requested one, otherwise in the build directory
+fn artifact_path(options: &Options, input: &Input, emit: Emit) -> PathBuf {
+    if emit == options.emit {
+        if let Some(output) = &options.output {
+            return output.clone();
+        }
+    }
+    let extension = match emit {
+        Emit::Asm => ".asm",
+        Emit::Obj => ".o",
+        _ => ""
+    };
+    options.build_dir.join(format!("{}{extension}", input.stem))
+}
+
+/// Assembles, and links unless an object file was requested, returning the path of the result
+fn build(options: &Options, input: &Input, code: &str) -> Result<PathBuf, CLIError<'static>> {
+    fs::create_dir_all(&options.build_dir)?;
+    let asm = artifact_path(options, input, Emit::Asm);
+    let object = artifact_path(options, input, Emit::Obj);
+    fs::write(&asm, code)?;
+
+    eprint!("  \x1b[1;34m Building \x1b[0m binary...\r");
     /* Assembler
-        nasm -f <elf64 | win64> output.asm
+        nasm -f <elf64 | win64> -o output.o output.asm
     */
-    process::Command::new("nasm")
-        .arg(
-            if cfg!(target_family = "windows") {r"-fwin64"} else {r"-felf64"}
-        )
-        .arg("build/output.asm")
-        .spawn()?
-        .wait()?;
+    let mut nasm = process::Command::new("nasm");
+    nasm.arg(if cfg!(target_family = "windows") {r"-fwin64"} else {r"-felf64"})
+        .arg("-o")
+        .arg(&object)
+        .arg(&asm);
+    run_tool("nasm", &mut nasm)?;
+    remove_temp(options, &asm)?;
+
+    if options.emit == Emit::Obj {
+        return Ok(object);
+    }
 
+    let binary = artifact_path(options, input, Emit::Exe);
     /* Linker
     Linux: GNU Linker (ld)
     */
     #[cfg(target_family = "unix")]
-    process::Command::new("ld")
-        .arg("-o")
-        .arg("build/output")
-        .arg("build/output.o")
-        .spawn()?
-        .wait()?;
+    {
+        let mut ld = process::Command::new("ld");
+        ld.arg("-o").arg(&binary).arg(&object);
+        run_tool("ld", &mut ld)?;
+        remove_temp(options, &object)?;
+    }
 
-    println!("  \x1b[1;32m Finished \x1b[0m compiling '{}' successfully", &args[1]);
+    Ok(binary)
+}
+
+fn run_tool(tool: &str, command: &mut process::Command) -> Result<(), CLIError<'static>> {
+    let status = command.status().map_err(|err| CLIError::Tool(tool.to_string(), err))?;
+    if !status.success() {
+        return Err(CLIError::ToolFailed(tool.to_string(), status));
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Removes an intermediate file, unless `--keep-temps` was given
+fn remove_temp(options: &Options, path: &Path) -> io::Result<()> {
+    if options.keep_temps {
+        return Ok(());
+    }
+    fs::remove_file(path)
+}
+
+/// Runs a compiled program, exiting with its exit code
+fn execute(binary: &Path) -> Result<ExitCode, CLIError<'static>> {
+    eprintln!("   \x1b[1;32m Running \x1b[0m '{}'", binary.display());
+    // Relative paths without a directory would otherwise be looked up in PATH
+    let binary = match binary.is_relative() {
+        true => Path::new(".").join(binary),
+        false => binary.to_path_buf()
+    };
+    let status = process::Command::new(&binary)
+        .status()
+        .map_err(|err| CLIError::Tool(binary.display().to_string(), err))?;
+    match status.code() {
+        Some(code) => Ok(ExitCode::from(code as u8)),
+        None => Err(CLIError::Terminated(status))
+    }
+}
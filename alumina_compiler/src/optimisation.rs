@@ -0,0 +1,447 @@
+//! Optimisation passes over the intermediate representation in SSA form
+//!
+//! Each pass reports whether it changed anything, and they are run in turn until none of them do.
+
+use std::collections::{HashMap, HashSet};
+use std::iter;
+
+use crate::ir::{Block, BlockId, Function, Instr, Operand, Program, Terminator, VReg};
+use crate::parser::{BinaryOp, UnaryOp};
+use crate::ssa::{self, Dominators};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OptLevel {
+	/// Generates code straight from the lowered program
+	O0,
+	/// Constant propagation, dead code elimination and merging blocks
+	O1,
+	/// Also common subexpression elimination and loop-invariant code motion
+	O2
+}
+
+/// Rounds of passes before giving up on reaching a fixed point
+const MAX_ROUNDS: usize = 16;
+
+/// Optimises every function of a program, leaving them in SSA form unless the level is `O0`
+pub fn optimise(program: &mut Program, level: OptLevel) {
+	if level == OptLevel::O0 {
+		return;
+	}
+	for function in iter::once(&mut program.main).chain(&mut program.functions) {
+		ssa::construct(function);
+		for _ in 0..MAX_ROUNDS {
+			let mut changed = propagate_constants(function);
+			changed |= eliminate_dead_code(function);
+			changed |= simplify_control_flow(function);
+			if level >= OptLevel::O2 {
+				changed |= eliminate_common_subexpressions(function);
+				changed |= hoist_loop_invariants(function);
+			}
+			if !changed {
+				break;
+			}
+		}
+	}
+}
+
+/// Result of a unary operator, matching the generated assembly
+pub(crate) fn fold_unary(op: UnaryOp, operand: i64) -> i64 {
+	match op {
+		UnaryOp::Neg => operand.wrapping_neg(),
+		UnaryOp::Not => (operand == 0) as i64
+	}
+}
+
+/// Result of a binary operator, or `None` for a division that traps at runtime
+pub(crate) fn fold_binary(op: BinaryOp, lhs: i64, rhs: i64) -> Option<i64> {
+	Some(match op {
+		BinaryOp::Add => lhs.wrapping_add(rhs),
+		BinaryOp::Sub => lhs.wrapping_sub(rhs),
+		BinaryOp::Mul => lhs.wrapping_mul(rhs),
+		BinaryOp::Div => return lhs.checked_div(rhs),
+		BinaryOp::Equal => (lhs == rhs) as i64,
+		BinaryOp::NotEqual => (lhs != rhs) as i64,
+		BinaryOp::Greater => (lhs > rhs) as i64,
+		BinaryOp::GreaterEqual => (lhs >= rhs) as i64,
+		BinaryOp::Less => (lhs < rhs) as i64,
+		BinaryOp::LessEqual => (lhs <= rhs) as i64,
+		BinaryOp::And => (lhs != 0 && rhs != 0) as i64,
+		BinaryOp::Or => (lhs != 0 || rhs != 0) as i64
+	})
+}
+
+/// Value an instruction always produces without being run, if it can be worked out from its operands
+fn known_value(instr: &Instr) -> Option<Operand> {
+	use crate::ir::Operand::Const;
+	match instr {
+		Instr::Copy { src, .. } => Some(*src),
+		Instr::Unary { op, operand: Const(value), .. } => Some(Const(fold_unary(*op, *value))),
+		Instr::Binary { op, lhs: Const(lhs), rhs: Const(rhs), .. } => fold_binary(*op, *lhs, *rhs).map(Const),
+		Instr::Binary { op, lhs, rhs, .. } => match (op, lhs, rhs) {
+			(BinaryOp::Add, operand, Const(0)) | (BinaryOp::Add, Const(0), operand) |
+			(BinaryOp::Sub, operand, Const(0)) |
+			(BinaryOp::Mul, operand, Const(1)) | (BinaryOp::Mul, Const(1), operand) |
+			(BinaryOp::Div, operand, Const(1)) => Some(*operand),
+			(BinaryOp::Mul, _, Const(0)) | (BinaryOp::Mul, Const(0), _) => Some(Const(0)),
+			(BinaryOp::Sub, lhs, rhs) if lhs == rhs => Some(Const(0)),
+			_ => None
+		},
+		Instr::Phi { dest, incoming } => {
+			// A phi reading itself around a loop is the same as its other values
+			let mut values = incoming.iter().map(|(_, value)| *value).filter(|value| *value != Operand::Reg(*dest));
+			let first = values.next()?;
+			values.all(|value| value == first).then_some(first)
+		},
+		_ => None
+	}
+}
+
+/// Replaces registers whose value is known with that value, and branches on constants with jumps
+///
+/// Instructions whose values were propagated are removed, along with blocks that can no longer be reached
+fn propagate_constants(function: &mut Function) -> bool {
+	let mut changed = false;
+	loop {
+		let mut replacements: HashMap<VReg, Operand> = HashMap::new();
+		for instr in function.blocks.iter().flat_map(|block| &block.instrs) {
+			let Some(mut value) = known_value(instr) else { continue };
+			while let Some(replacement) = value.reg().and_then(|reg| replacements.get(&reg)) {
+				value = *replacement;
+			}
+			// Only reads itself, which is never the case on a path where it is read
+			if value != Operand::Reg(instr.dest()) {
+				replacements.insert(instr.dest(), value);
+			}
+		}
+		if replacements.is_empty() {
+			break;
+		}
+		for block in &mut function.blocks {
+			block.instrs.retain(|instr| !replacements.contains_key(&instr.dest()));
+		}
+		function.replace_uses(&replacements);
+		changed = true;
+	}
+
+	for index in 0..function.blocks.len() {
+		let Terminator::Branch { condition: Operand::Const(value), then, otherwise } = function.blocks[index].terminator else { continue };
+		let (taken, dropped) = if value != 0 { (then, otherwise) } else { (otherwise, then) };
+		function.blocks[index].terminator = Terminator::Jump(taken);
+		if taken != dropped {
+			remove_incoming(&mut function.blocks[dropped.0], BlockId(index));
+		}
+		changed = true;
+	}
+	function.remove_unreachable_blocks() || changed
+}
+
+/// Drops the values phis take when coming from a block that no longer jumps to theirs
+fn remove_incoming(block: &mut Block, pred: BlockId) {
+	for instr in &mut block.instrs {
+		if let Instr::Phi { incoming, .. } = instr {
+			incoming.retain(|(block, _)| *block != pred);
+		}
+	}
+}
+
+/// Removes instructions whose results are never read and that have no side effects
+fn eliminate_dead_code(function: &mut Function) -> bool {
+	let mut definitions: HashMap<VReg, &Instr> = HashMap::new();
+	let mut live: HashSet<VReg> = HashSet::new();
+	let mut worklist: Vec<Operand> = Vec::new();
+	for block in &function.blocks {
+		for instr in &block.instrs {
+			definitions.insert(instr.dest(), instr);
+			if instr.has_side_effects() {
+				worklist.extend(instr.operands());
+			}
+		}
+		worklist.extend(block.terminator.operand());
+	}
+	while let Some(operand) = worklist.pop() {
+		let Operand::Reg(reg) = operand else { continue };
+		if live.insert(reg) {
+			if let Some(instr) = definitions.get(&reg) {
+				worklist.extend(instr.operands());
+			}
+		}
+	}
+
+	let mut changed = false;
+	for block in &mut function.blocks {
+		let count = block.instrs.len();
+		block.instrs.retain(|instr| instr.has_side_effects() || live.contains(&instr.dest()));
+		changed |= block.instrs.len() != count;
+	}
+	changed
+}
+
+/// Merges blocks into their only predecessor, and skips over blocks that only jump elsewhere
+fn simplify_control_flow(function: &mut Function) -> bool {
+	let mut changed = false;
+
+	// Merges a block into its predecessor, when that jumps to it and nothing else does
+	loop {
+		let predecessors = function.predecessors();
+		let merge = (0..function.blocks.len()).find_map(|index| match function.blocks[index].terminator {
+			Terminator::Jump(successor) if successor.0 != 0 && successor.0 != index && predecessors[successor.0].len() == 1 => {
+				Some((index, successor))
+			},
+			_ => None
+		});
+		let Some((index, successor)) = merge else { break };
+
+		let placeholder = Block { instrs: Vec::new(), terminator: Terminator::Jump(successor) };
+		let merged = std::mem::replace(&mut function.blocks[successor.0], placeholder);
+		let instrs = merged.instrs.into_iter().map(|instr| match instr {
+			// The only value is the one from this predecessor
+			Instr::Phi { dest, incoming } => Instr::Copy { dest, src: incoming[0].1 },
+			instr => instr
+		});
+		function.blocks[index].instrs.extend(instrs);
+		for next in merged.terminator.successors() {
+			rename_incoming(&mut function.blocks[next.0], successor, BlockId(index));
+		}
+		function.blocks[index].terminator = merged.terminator;
+		changed = true;
+	}
+
+	// Sends the predecessors of empty blocks straight to where the empty block jumps
+	let predecessors = function.predecessors();
+	for (index, predecessors) in predecessors.iter().enumerate().skip(1) {
+		let Terminator::Jump(target) = function.blocks[index].terminator else { continue };
+		if target.0 == index || !function.blocks[index].instrs.is_empty() {
+			continue;
+		}
+		for &pred in predecessors {
+			// Phis in the target couldn't tell two edges from the same block apart
+			if function.blocks[pred.0].terminator.successors().contains(&target) {
+				continue;
+			}
+			for successor in function.blocks[pred.0].terminator.successors_mut() {
+				if successor.0 == index {
+					*successor = target;
+				}
+			}
+			for instr in &mut function.blocks[target.0].instrs {
+				if let Instr::Phi { incoming, .. } = instr {
+					let value = incoming.iter().find(|(block, _)| block.0 == index).map(|(_, value)| *value);
+					incoming.extend(value.map(|value| (pred, value)));
+				}
+			}
+			changed = true;
+		}
+	}
+
+	function.remove_unreachable_blocks() || changed
+}
+
+/// Points the phis of a block at a new predecessor, after the old one was merged into it
+fn rename_incoming(block: &mut Block, old: BlockId, new: BlockId) {
+	for instr in &mut block.instrs {
+		if let Instr::Phi { incoming, .. } = instr {
+			for (block, _) in incoming {
+				if *block == old {
+					*block = new;
+				}
+			}
+		}
+	}
+}
+
+/// Reuses the result of an identical computation that dominates an instruction
+///
+/// Divisions are included, as if the first traps the second never runs
+fn eliminate_common_subexpressions(function: &mut Function) -> bool {
+	let dominators = Dominators::new(function);
+	let children = dominators.children();
+	let mut replacements = HashMap::new();
+	let mut available = Vec::new();
+	// Blocks to visit, and the number of available expressions to go back to after leaving each
+	let mut stack = vec![(BlockId(0), None)];
+	while let Some((block, leaving)) = stack.pop() {
+		if let Some(length) = leaving {
+			available.truncate(length);
+			continue;
+		}
+		stack.push((block, Some(available.len())));
+		for instr in &function.blocks[block.0].instrs {
+			let Some(key) = expression_key(instr) else { continue };
+			match available.iter().find(|(existing, _)| *existing == key) {
+				Some((_, reg)) => {
+					replacements.insert(instr.dest(), Operand::Reg(*reg));
+				},
+				None => available.push((key, instr.dest()))
+			}
+		}
+		stack.extend(children[block.0].iter().map(|child| (*child, None)));
+	}
+
+	if replacements.is_empty() {
+		return false;
+	}
+	for block in &mut function.blocks {
+		block.instrs.retain(|instr| !replacements.contains_key(&instr.dest()));
+	}
+	function.replace_uses(&replacements);
+	true
+}
+
+/// An arithmetic instruction without its destination, with the operands of commutative operators in a fixed order
+fn expression_key(instr: &Instr) -> Option<Instr> {
+	let dest = VReg(0);
+	match *instr {
+		Instr::Unary { op, operand, .. } => Some(Instr::Unary { dest, op, operand }),
+		Instr::Binary { op, lhs, rhs, .. } => {
+			let commutative = matches!(op, BinaryOp::Add | BinaryOp::Mul | BinaryOp::Equal | BinaryOp::NotEqual);
+			let swap = commutative && match (lhs, rhs) {
+				(Operand::Const(_), Operand::Reg(_)) => true,
+				(Operand::Reg(lhs), Operand::Reg(rhs)) => lhs > rhs,
+				_ => false
+			};
+			let (lhs, rhs) = if swap { (rhs, lhs) } else { (lhs, rhs) };
+			Some(Instr::Binary { dest, op, lhs, rhs })
+		},
+		_ => None
+	}
+}
+
+/// Moves instructions that compute the same value on every iteration of a loop to just before it
+///
+/// The instructions go on the header's only predecessor from outside the loop if it only jumps to the header,
+/// otherwise on a new block in front of the header
+fn hoist_loop_invariants(function: &mut Function) -> bool {
+	let mut changed = false;
+	// Each hoist may make more hoisting possible in a loop around the one it hoisted from
+	for _ in 0..MAX_ROUNDS {
+		let dominators = Dominators::new(function);
+		let predecessors = function.predecessors();
+		let mut hoisted = None;
+		for &header in dominators.order() {
+			let latches: Vec<BlockId> = predecessors[header.0].iter()
+				.filter(|pred| dominators.dominates(header, **pred))
+				.copied()
+				.collect();
+			if latches.is_empty() {
+				continue;
+			}
+			let body = loop_body(&predecessors, header, &latches);
+			let instrs = loop_invariants(function, &body);
+			if !instrs.is_empty() {
+				hoisted = Some((header, body, instrs));
+				break;
+			}
+		}
+		let Some((header, body, order)) = hoisted else { break };
+
+		let mut moved = HashMap::new();
+		for block in &body {
+			let instrs = &mut function.blocks[block.0].instrs;
+			for instr in instrs.extract_if(.., |instr| order.contains(&instr.dest())) {
+				moved.insert(instr.dest(), instr);
+			}
+		}
+		let instrs = order.iter().map(|reg| moved.remove(reg).unwrap()).collect();
+		insert_preheader(function, header, &body, instrs);
+		changed = true;
+	}
+	changed
+}
+
+/// Blocks of the loop with a header and the blocks that jump back to it
+fn loop_body(predecessors: &[Vec<BlockId>], header: BlockId, latches: &[BlockId]) -> HashSet<BlockId> {
+	let mut body = HashSet::from([header]);
+	let mut worklist = latches.to_vec();
+	while let Some(block) = worklist.pop() {
+		if body.insert(block) {
+			worklist.extend(&predecessors[block.0]);
+		}
+	}
+	body
+}
+
+/// Results of the pure instructions in a loop whose operands are all computed before it, in the order they can be run
+fn loop_invariants(function: &Function, body: &HashSet<BlockId>) -> Vec<VReg> {
+	let defined_inside: HashSet<VReg> = body.iter()
+		.flat_map(|block| &function.blocks[block.0].instrs)
+		.map(Instr::dest)
+		.collect();
+	let mut invariant: HashSet<VReg> = HashSet::new();
+	let mut found = Vec::new();
+	let mut changed = true;
+	while changed {
+		changed = false;
+		for instr in body.iter().flat_map(|block| &function.blocks[block.0].instrs) {
+			let pure = matches!(instr, Instr::Unary { .. } | Instr::Binary { .. }) && !instr.has_side_effects();
+			if !pure || invariant.contains(&instr.dest()) {
+				continue;
+			}
+			let operands_invariant = instr.operands().iter().all(|operand| match operand {
+				Operand::Reg(reg) => !defined_inside.contains(reg) || invariant.contains(reg),
+				Operand::Const(_) => true
+			});
+			if operands_invariant {
+				invariant.insert(instr.dest());
+				found.push(instr.dest());
+				changed = true;
+			}
+		}
+	}
+	found
+}
+
+/// Puts instructions on the way into a loop, on a new block in front of its header if there isn't one to reuse
+fn insert_preheader(function: &mut Function, header: BlockId, body: &HashSet<BlockId>, instrs: Vec<Instr>) {
+	let entries: Vec<BlockId> = function.predecessors()[header.0].iter()
+		.filter(|pred| !body.contains(pred))
+		.copied()
+		.collect();
+	if let [pred] = entries[..] {
+		if function.blocks[pred.0].terminator == Terminator::Jump(header) {
+			function.blocks[pred.0].instrs.extend(instrs);
+			return;
+		}
+	}
+
+	// Values from outside the loop now come through the preheader, joined there if there are several
+	let mut preheader_phis = Vec::new();
+	let mut values = Vec::new();
+	let mut reg_count = function.reg_count;
+	for instr in &mut function.blocks[header.0].instrs {
+		let Instr::Phi { incoming, .. } = instr else { continue };
+		let (outside, inside): (Vec<_>, Vec<_>) = incoming.drain(..).partition(|(block, _)| entries.contains(block));
+		*incoming = inside;
+		values.push(match outside[..] {
+			[(_, value)] => value,
+			_ => {
+				let dest = VReg(reg_count);
+				reg_count += 1;
+				preheader_phis.push(Instr::Phi { dest, incoming: outside });
+				Operand::Reg(dest)
+			}
+		});
+	}
+	function.reg_count = reg_count;
+
+	let preheader = function.insert_block(header.0, Block {
+		instrs: preheader_phis.into_iter().chain(instrs).collect(),
+		terminator: Terminator::Jump(header)
+	});
+	// The header and every block after it moved along by one, so the edges from the preheader are added after
+	let header = BlockId(header.0 + 1);
+	let phis = function.blocks[header.0].instrs.iter_mut().filter_map(|instr| match instr {
+		Instr::Phi { incoming, .. } => Some(incoming),
+		_ => None
+	});
+	for (incoming, value) in phis.zip(values) {
+		incoming.push((preheader, value));
+	}
+	let renumbered = |block: BlockId| if block.0 >= preheader.0 { BlockId(block.0 + 1) } else { block };
+	for pred in entries.into_iter().map(renumbered) {
+		for successor in function.blocks[pred.0].terminator.successors_mut() {
+			if *successor == header {
+				*successor = preheader;
+			}
+		}
+	}
+}
@@ -1,199 +1,545 @@
+use std::convert::TryFrom;
+use std::fmt;
 use std::iter::Peekable;
 
-use crate::token::Token;
-
-
-#[derive(Debug, Clone)]
-pub enum NodeType {
-	BlockStart,
-	BlockEnd,
-	StmtFunction(String),
-	StmtNewVar(String),
-	StmtAssign(String),
-	// StmtReassign(String),
-	StmtIf(usize),
-	StmtWhile,
-	ExprIdent(String),
-	ExprLiteral(u32),
-	ExprParen,
-	ExprBinAdd,
-	ExprBinSub,
-	ExprBinMul,
-	ExprBinDiv,
-	ExprEqual,
-	ExprNotEqual,
-	ExprGreater,
-	ExprLessEqual,
-	ExprLess,
-	ExprGreaterEqual
+use flat_tree::{FlatTree, TreeNode};
+pub use flat_tree::NodeId;
+
+use crate::token::{Span, SpannedToken, Token};
+
+
+/// What a node is, stored in the tree
+///
+/// A node's operands, arms and statements are its children in the tree, in source order
+#[derive(Debug, Clone, PartialEq)]
+pub enum NodeKind {
+	Block,
+	Exit,
+	Let(String),
+	Assign(String),
+	/// Has a condition, a block and optionally an else block
+	If,
+	While,
+	Function {
+		name: String,
+		params: Vec<String>
+	},
+	Return,
+	Expr,
+	Ident(String),
+	Literal(i64),
+	Call(String),
+	Unary(UnaryOp),
+	Binary(BinaryOp)
 }
 
-#[derive(Debug, Clone)]
-pub struct Node {
-	pub variant: NodeType,
-	pub parent: Option<usize>,
+/// A node along with its children, read from the tree by [`Ast::variant`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum NodeType<'a> {
+	/// Statements run in order, in their own scope
+	Block(Vec<NodeId>),
+	Stmt(Stmt<'a>),
+	Expr(Expr<'a>)
 }
-impl std::fmt::Display for Node {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-		if let Some(parent) = self.parent {
-			write!(f, "{:?}, Parent: {parent}", self.variant)
-		} else {
-			write!(f, "{:?}", self.variant)
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stmt<'a> {
+	/// The builtin `exit`
+	Exit(NodeId),
+	/// Declaration of a new variable
+	Let(&'a str, NodeId),
+	Assign(&'a str, NodeId),
+	If {
+		condition: NodeId,
+		then_block: NodeId,
+		else_block: Option<NodeId>
+	},
+	While {
+		condition: NodeId,
+		body: NodeId
+	},
+	/// Definition of a function, its parameters and its body
+	Function {
+		name: &'a str,
+		params: &'a [String],
+		body: NodeId
+	},
+	Return(NodeId),
+	/// Expression evaluated only for its side effects
+	Expr(NodeId)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr<'a> {
+	Ident(&'a str),
+	Literal(i64),
+	/// Call of a function with its arguments, in order
+	Call(&'a str, Vec<NodeId>),
+	Unary(UnaryOp, NodeId),
+	/// Operator with its left and right operands
+	Binary(BinaryOp, NodeId, NodeId)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+	Neg,
+	Not
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+	Add,
+	Sub,
+	Mul,
+	Div,
+	Equal,
+	NotEqual,
+	Greater,
+	GreaterEqual,
+	Less,
+	LessEqual,
+	/// Short circuiting `&&`
+	And,
+	/// Short circuiting `||`
+	Or
+}
+
+impl fmt::Display for NodeKind {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			NodeKind::Block => write!(f, "Block"),
+			NodeKind::Exit => write!(f, "Exit"),
+			NodeKind::Let(name) => write!(f, "Let `{name}`"),
+			NodeKind::Assign(name) => write!(f, "Assign `{name}`"),
+			NodeKind::If => write!(f, "If"),
+			NodeKind::While => write!(f, "While"),
+			NodeKind::Function { name, params } => write!(f, "Function `{name}({})`", params.join(", ")),
+			NodeKind::Return => write!(f, "Return"),
+			NodeKind::Expr => write!(f, "Expr"),
+			NodeKind::Ident(name) => write!(f, "Ident `{name}`"),
+			NodeKind::Literal(value) => write!(f, "Literal {value}"),
+			NodeKind::Call(name) => write!(f, "Call `{name}`"),
+			NodeKind::Unary(op) => write!(f, "{op:?}"),
+			NodeKind::Binary(op) => write!(f, "{op:?}"),
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AstNode {
+	pub kind: NodeKind,
+	pub span: Span,
+}
+
+/// A parsed program
+/// 
+/// Children are added to the tree before their parents, and each node records its parent.
+/// The root is a [`NodeKind::Block`] of the top level statements
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ast {
+	pub tree: FlatTree<AstNode>,
+	pub root: NodeId
+}
+impl Ast {
+	pub fn get(&self, id: NodeId) -> &AstNode {
+		&self.tree[id]
+	}
+
+	/// Ids of a node's children, in source order
+	pub fn children(&self, id: NodeId) -> Vec<NodeId> {
+		self.tree.get(id).children().map(|child| child.get_id()).collect()
+	}
+
+	/// A node's kind along with the ids of its children
+	pub fn variant(&self, id: NodeId) -> NodeType<'_> {
+		let node = self.tree.get(id);
+		let mut children = node.children().map(|child| child.get_id());
+		let mut child = || children.next().expect("Node is missing a child");
+		match &self.tree[id].kind {
+			NodeKind::Block => NodeType::Block(self.children(id)),
+			NodeKind::Exit => NodeType::Stmt(Stmt::Exit(child())),
+			NodeKind::Let(name) => NodeType::Stmt(Stmt::Let(name, child())),
+			NodeKind::Assign(name) => NodeType::Stmt(Stmt::Assign(name, child())),
+			NodeKind::If => NodeType::Stmt(Stmt::If { condition: child(), then_block: child(), else_block: children.next() }),
+			NodeKind::While => NodeType::Stmt(Stmt::While { condition: child(), body: child() }),
+			NodeKind::Function { name, params } => NodeType::Stmt(Stmt::Function { name, params, body: child() }),
+			NodeKind::Return => NodeType::Stmt(Stmt::Return(child())),
+			NodeKind::Expr => NodeType::Stmt(Stmt::Expr(child())),
+			NodeKind::Ident(name) => NodeType::Expr(Expr::Ident(name)),
+			NodeKind::Literal(value) => NodeType::Expr(Expr::Literal(*value)),
+			NodeKind::Call(name) => NodeType::Expr(Expr::Call(name, self.children(id))),
+			NodeKind::Unary(op) => NodeType::Expr(Expr::Unary(*op, child())),
+			NodeKind::Binary(op) => NodeType::Expr(Expr::Binary(*op, child(), child()))
 		}
-    }
+	}
+
+	/// Renders the program as a Graphviz DOT digraph, one box per node
+	pub fn to_dot(&self) -> String {
+		self.tree.render_dot(self.root, AstNode::label)
+	}
+}
+
+impl AstNode {
+	fn label(&self) -> String {
+		format!("{} at {}", self.kind, self.span)
+	}
+}
+
+impl fmt::Display for Ast {
+	/// Prints each statement of the program as an ASCII tree
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		for statement in self.tree.get(self.root).children() {
+			f.write_str(&self.tree.render_ascii(statement.get_id(), AstNode::label))?;
+		}
+		Ok(())
+	}
 }
 
 #[derive(Debug)]
-pub enum ParserError {
+pub enum ParserError<'a> {
     EndOfInput,
 	EndOfBlock,
-	UnexpectedToken
+	/// An integer literal too large for an `i64`
+	LiteralOutOfRange(Span),
+	UnexpectedToken {
+		/// Description of what would have been valid
+		expected: String,
+		/// `None` at the end of input
+		found: Option<Token<'a>>,
+		span: Span
+	}
+}
+
+/// Entries of the operator stack while parsing an expression
+#[derive(Debug, Clone, Copy)]
+enum Operator {
+	/// An opening parenthesis
+	Paren,
+	Unary(UnaryOp),
+	Binary(BinaryOp)
+}
+impl Operator {
+	fn precedence(self) -> usize {
+		match self {
+			Operator::Paren => 0,
+			Operator::Unary(_) => 6,
+			Operator::Binary(op) => match op {
+				BinaryOp::Mul | BinaryOp::Div => 5,
+				BinaryOp::Add | BinaryOp::Sub => 4,
+				BinaryOp::Equal | BinaryOp::NotEqual |
+				BinaryOp::Greater | BinaryOp::GreaterEqual |
+				BinaryOp::Less | BinaryOp::LessEqual => 3,
+				BinaryOp::And => 2,
+				BinaryOp::Or => 1,
+			}
+		}
+	}
 }
 
-pub struct Parser<I: Iterator<Item = Token>> {
+pub struct Parser<'a, I: Iterator<Item = SpannedToken<'a>>> {
     input: Peekable<I>,
-    nodes: Vec<Node>,
-	blocks: Vec<usize>
+    tree: FlatTree<AstNode>,
+	/// Nodes that don't have a parent yet, most recent last
+	unattached: Vec<NodeId>,
+	errors: Vec<ParserError<'a>>,
+	/// Span of the most recently consumed token
+	span: Span
 }
 	
-impl <I: Iterator<Item = Token>> Parser<I> {
-    pub fn parse(iterator: I) -> Result<Vec<Node>, ParserError> {
+impl <'a, I: Iterator<Item = SpannedToken<'a>>> Parser<'a, I> {
+	/// Parses the whole input, recovering from errors at statement boundaries
+	/// 
+	/// Returns the tree of everything that was parsed successfully along with all errors found.
+	/// Statements containing errors are left out of their block
+    pub fn parse(iterator: I) -> (Ast, Vec<ParserError<'a>>) {
 
 		let input = iterator.peekable();
 
-		let mut parser: Parser<I> = Parser {
+		let mut parser: Parser<'a, I> = Parser {
 			input,
-			nodes: Vec::new(),
-			blocks: Vec::new()
+			tree: FlatTree::new(),
+			unattached: Vec::new(),
+			errors: Vec::new(),
+			span: Span::default()
 		};
 
+		let mut statements = Vec::new();
 		loop {
-			match parser.parse_node() {
-				Ok(_) => (),
+			match parser.parse_statement() {
+				Ok(Some(statement)) => statements.push(statement),
+				Ok(None) => (),
 				Err(ParserError::EndOfInput) => break,
-				Err(err) => return Err(err),
+				Err(ParserError::EndOfBlock) => {
+					// Unmatched closing brace
+					let err = parser.unexpected("a statement");
+					parser.next_token();
+					parser.errors.push(err);
+				},
+				Err(err) => parser.errors.push(err),
 			}
 		}
 
-		Ok(parser.nodes)
-	}
-
-	fn parse_node(&mut self) -> Result<(), ParserError> {
-		match self.input.peek() {
-			Some(Token::LBrace) => self.parse_block(),
-			Some(Token::Let) => self.parse_assignment(),
-			Some(Token::If) => self.parse_conditional(),
-			Some(Token::While) => self.parse_loop(),
-			Some(Token::Exit) => self.parse_function(),
-			Some(Token::Ident(_)) => self.parse_reassignment(),
-			Some(Token::Sep) => { self.input.next(); Ok(()) },
-			Some(Token::RBrace) => Err(ParserError::EndOfBlock),
-			Some(_) => Err(ParserError::UnexpectedToken),
-			None => Err(ParserError::EndOfInput)
+		let root = parser.add_node(NodeKind::Block, &statements, Span::default());
+		(Ast { tree: parser.tree, root }, parser.errors)
+	}
+
+	/// Adds a node to the tree as the parent of its children
+	///
+	/// The children must be the most recently added nodes without a parent, in order
+	fn add_node(&mut self, kind: NodeKind, children: &[NodeId], span: Span) -> NodeId {
+		let start = self.unattached.len() - children.len();
+		assert_eq!(&self.unattached[start..], children, "Children must be the last nodes without a parent");
+		self.unattached.truncate(start);
+
+		let id = self.tree.add(AstNode { kind, span }).get_id();
+		for &child in children {
+			self.tree.set_parent(child, id);
 		}
+		self.unattached.push(id);
+		id
 	}
 
-	fn parse_block(&mut self) -> Result<(), ParserError> {
-		match self.input.next() {
-			Some(Token::LBrace) => (),
-			 _ => return Err(ParserError::UnexpectedToken)
+	fn peek_token(&mut self) -> Option<&Token<'a>> {
+		self.input.peek().map(|spanned| &spanned.token)
+	}
+
+	fn next_token(&mut self) -> Option<Token<'a>> {
+		let SpannedToken { token, span } = self.input.next()?;
+		self.span = span;
+		Some(token)
+	}
+
+	fn next_token_if_eq(&mut self, expected: &Token<'a>) -> Option<Token<'a>> {
+		match self.peek_token() {
+			Some(token) if token == expected => self.next_token(),
+			_ => None
+		}
+	}
+
+	/// Error for the next token in the input, without consuming it
+	fn unexpected(&mut self, expected: impl Into<String>) -> ParserError<'a> {
+		let (found, span) = match self.input.peek() {
+			Some(SpannedToken { token, span }) => (Some(token.clone()), *span),
+			// Point just past the last token
+			None => (None, Span {
+				offset: self.span.offset + self.span.len,
+				column: self.span.column + self.span.len,
+				len: 0,
+				..self.span
+			})
 		};
+		ParserError::UnexpectedToken { expected: expected.into(), found, span }
+	}
 
-		self.nodes.push(Node {
-			variant: NodeType::BlockStart,
-			parent: self.blocks.last().copied()
-		});
+	fn expect(&mut self, token: Token<'a>) -> Result<(), ParserError<'a>> {
+		match self.next_token_if_eq(&token) {
+			Some(_) => Ok(()),
+			None => Err(self.unexpected(format!("`{token}`")))
+		}
+	}
 
-		self.blocks.push(self.nodes.len() - 1);
+	/// Takes an identifier's name, copying it out of the source if it was borrowed
+	fn expect_ident(&mut self) -> Result<String, ParserError<'a>> {
+		match self.peek_token() {
+			Some(Token::Ident(_)) => match self.next_token() {
+				Some(Token::Ident(ident)) => Ok(ident.into_owned()),
+				_ => unreachable!()
+			},
+			_ => Err(self.unexpected("an identifier"))
+		}
+	}
+
+	/// Statements end at a separator, a closing brace or the end of input
+	fn expect_end_of_statement(&mut self) -> Result<(), ParserError<'a>> {
+		match self.peek_token() {
+			Some(Token::Sep) => { self.next_token(); Ok(()) },
+			Some(Token::RBrace) | None => Ok(()),
+			Some(_) => Err(self.unexpected("`;` or a new line"))
+		}
+	}
+
+	/// Parses a statement, recording any error and skipping to the next statement
+	/// 
+	/// Nodes from a statement that failed to parse are removed from the tree
+	fn parse_statement(&mut self) -> Result<Option<NodeId>, ParserError<'a>> {
+		let checkpoint = self.unattached.len();
+		match self.parse_node() {
+			Err(err @ ParserError::UnexpectedToken { .. }) => {
+				for id in self.unattached.drain(checkpoint..) {
+					self.tree.remove_subtree(id);
+				}
+
+
+				// Unclosed blocks report the end of input once, not once per block
+				let repeated_end = matches!(
+					(&err, self.errors.last()),
+					(ParserError::UnexpectedToken { found: None, .. }, Some(ParserError::UnexpectedToken { found: None, .. }))
+				);
+				if !repeated_end {
+					self.errors.push(err);
+				}
+
+				self.synchronize();
+				Ok(None)
+			},
+			result => result
+		}
+	}
+
+	/// Skips tokens until the end of the current statement
+	/// 
+	/// Consumes up to and including the next separator, stopping early before a
+	/// closing brace that would close the enclosing block
+	fn synchronize(&mut self) {
+		let mut depth = 0;
+		while let Some(token) = self.peek_token() {
+			match token {
+				Token::Sep if depth == 0 => {
+					self.next_token();
+					return;
+				},
+				Token::RBrace if depth == 0 => return,
+				Token::RBrace => depth -= 1,
+				Token::LBrace => depth += 1,
+				_ => ()
+			}
+			self.next_token();
+		}
+	}
+
+	/// Parses the next statement, returning `None` for an empty statement
+	fn parse_node(&mut self) -> Result<Option<NodeId>, ParserError<'a>> {
+		let statement = match self.peek_token() {
+			Some(Token::LBrace) => self.parse_block()?,
+			Some(Token::Let) => self.parse_assignment()?,
+			Some(Token::If) => self.parse_conditional()?,
+			Some(Token::While) => self.parse_loop()?,
+			Some(Token::Exit) => self.parse_function()?,
+			Some(Token::Fn) => self.parse_function_definition()?,
+			Some(Token::Return) => self.parse_return()?,
+			Some(Token::Ident(_)) => self.parse_reassignment()?,
+			Some(Token::Sep) => { self.next_token(); return Ok(None) },
+			Some(Token::RBrace) => return Err(ParserError::EndOfBlock),
+			Some(_) => return Err(self.unexpected("a statement")),
+			None => return Err(ParserError::EndOfInput)
+		};
+		Ok(Some(statement))
+	}
+
+	/// Parses a block
+	/// 
+	/// Expects:
+	/// { <statement>[0+] }
+	/// 
+	/// Returns:
+	/// Block(<statement>[0+])
+	fn parse_block(&mut self) -> Result<NodeId, ParserError<'a>> {
+		self.expect(Token::LBrace)?;
+		let span = self.span;
 
+		let mut statements = Vec::new();
 		loop {
-			match self.parse_node() {
-				Ok(_) => (),
+			match self.parse_statement() {
+				Ok(Some(statement)) => statements.push(statement),
+				Ok(None) => (),
 				Err(ParserError::EndOfBlock) => break,
+				Err(ParserError::EndOfInput) => return Err(self.unexpected("`}`")),
 				Err(err) => return Err(err),
 			}
 		}
 
-		match self.input.next() {
-			Some(Token::RBrace) => (),
-			 _ => return Err(ParserError::UnexpectedToken)
-		};
+		self.expect(Token::RBrace)?;
 
-		self.nodes.push(Node {
-			variant: NodeType::BlockEnd,
-			parent: self.blocks.last().copied()
-		});
+		Ok(self.add_node(NodeKind::Block, &statements, span))
+	}
 
-		self.blocks.pop();
+	/// Parses a call to the builtin `exit`
+	/// 
+	/// Expects:
+	/// exit <expr>
+	/// 
+	/// Returns:
+	/// Exit(<expr>)
+	fn parse_function(&mut self) -> Result<NodeId, ParserError<'a>> {
+		self.expect(Token::Exit)?;
+		let span = self.span;
 
-		Ok(())
+		let value = self.parse_expression()?;
+
+		self.expect_end_of_statement()?;
+		Ok(self.add_node(NodeKind::Exit, &[value], span))
 	}
 
-	/// Parses a function
-	/// Currently only handles hardcoded exit
+	/// Parses a function definition
 	/// 
 	/// Expects:
-	/// <ident> <expr>[1+]
+	/// fn <ident> ( <ident>[0+, comma separated] ) <block>
 	/// 
 	/// Returns:
-	/// <function> <expr>
-	fn parse_function(&mut self) -> Result<(), ParserError> {
+	/// Function(<block>)
+	fn parse_function_definition(&mut self) -> Result<NodeId, ParserError<'a>> {
+		self.expect(Token::Fn)?;
+		let span = self.span;
+
+		let name = self.expect_ident()?;
+
+		self.expect(Token::LParen)?;
+		let mut params = Vec::new();
+		if self.next_token_if_eq(&Token::RParen).is_none() {
+			loop {
+				params.push(self.expect_ident()?);
+				if self.next_token_if_eq(&Token::Comma).is_some() {
+					continue;
+				}
+				self.expect(Token::RParen)?;
+				break;
+			}
+		}
 
-		match self.input.next() {
-			Some(Token::Exit) => (),
-			 _ => return Err(ParserError::UnexpectedToken)
-		};
+		let body = self.parse_block()?;
 
-		self.nodes.push(Node {
-			variant: NodeType::StmtFunction(String::from("exit")),
-			parent: self.blocks.last().copied()
-		});
-		let index = self.nodes.len() - 1;
+		Ok(self.add_node(NodeKind::Function { name, params }, &[body], span))
+	}
+
+	/// Parses a return statement
+	/// 
+	/// Expects:
+	/// return <expr>
+	/// 
+	/// Returns:
+	/// Return(<expr>)
+	fn parse_return(&mut self) -> Result<NodeId, ParserError<'a>> {
+		self.expect(Token::Return)?;
+		let span = self.span;
 
-		self.parse_expression()?;
-		self.nodes.last_mut().unwrap().parent = Some(index);
+		let value = self.parse_expression()?;
 
-		Ok(())
+		self.expect_end_of_statement()?;
+		Ok(self.add_node(NodeKind::Return, &[value], span))
 	}
 
-	///	Parses a conditional statement from input
+	/// Parses a conditional statement from input
 	/// 
 	/// Expects:
 	/// - if <expr> <block>
 	/// - if <expr> <block> else <block>
 	/// 
 	/// Returns:
-	/// - <if> <expr> <block>[1/2]
+	/// - If(<expr>, <block>, <block>?)
 	/// 
-	fn parse_conditional(&mut self) -> Result<(), ParserError> {
-		match self.input.next() {
-			Some(Token::If) => (),
-			 _ => return Err(ParserError::UnexpectedToken)
-		};
-
-		self.nodes.push(Node {
-			variant: NodeType::StmtIf(0),
-			parent: self.blocks.last().copied()
-		});
-		let index = self.nodes.len() - 1;
-
-		self.parse_expression()?;
-		self.nodes.last_mut().unwrap().parent = Some(index);
+	fn parse_conditional(&mut self) -> Result<NodeId, ParserError<'a>> {
+		self.expect(Token::If)?;
+		let span = self.span;
 
-		self.parse_block()?;
-		self.nodes.last_mut().unwrap().parent = Some(index);
+		let condition = self.parse_expression()?;
+		let then_block = self.parse_block()?;
 
-		// Else
-		match self.input.next_if_eq(&Token::Else) {
-			None => return Ok(()),
-			_ => ()
+		let mut children = vec![condition, then_block];
+		if self.next_token_if_eq(&Token::Else).is_some() {
+			children.push(self.parse_block()?);
 		}
-		self.nodes.get_mut(index).unwrap().variant = NodeType::StmtIf(1);
 
-		self.parse_block()?;
-		self.nodes.last_mut().unwrap().parent = Some(index);
-
-		Ok(())
+		Ok(self.add_node(NodeKind::If, &children, span))
 	}
 
 	/// Parses an assignement expression
@@ -202,34 +548,20 @@ impl <I: Iterator<Item = Token>> Parser<I> {
 	/// let <ident> = <expr>
 	/// 
 	/// Returns:
-	/// - StmtNewVar(<ident>)
-	/// - Expr
-	fn parse_assignment(&mut self) -> Result<(), ParserError> {
+	/// Let(<ident>, <expr>)
+	fn parse_assignment(&mut self) -> Result<NodeId, ParserError<'a>> {
 
-		match self.input.next() {
-			Some(Token::Let) => (),
-			 _ => return Err(ParserError::UnexpectedToken)
-		};
+		self.expect(Token::Let)?;
+		let span = self.span;
 
-		let ident_name = self.input.next()
-			.ok_or(ParserError::EndOfInput)
-			.and_then(|token| if let Token::Ident(value) = token { Ok(value) } else { Err(ParserError::EndOfInput) })?;
+		let ident_name = self.expect_ident()?;
 
-		match self.input.next() {
-			Some(Token::Equal) => (),
-			 _ => return Err(ParserError::UnexpectedToken)
-		};
+		self.expect(Token::Equal)?;
 
-		self.nodes.push(Node {
-			variant: NodeType::StmtNewVar(ident_name.to_string()),
-			parent: self.blocks.last().copied()
-		});
-		let index = self.nodes.len() - 1;
- 
-		self.parse_expression()?;
-		self.nodes.last_mut().unwrap().parent = Some(index);
+		let value = self.parse_expression()?;
 
-		Ok(())
+		self.expect_end_of_statement()?;
+		Ok(self.add_node(NodeKind::Let(ident_name), &[value], span))
 	}
 
 	/// Parses a loop from the input
@@ -237,163 +569,216 @@ impl <I: Iterator<Item = Token>> Parser<I> {
 	/// Expects
 	/// - while <expr> <block>
 	/// 
-	/// Returns (unusual)
-	/// - <while> <expr> <block> 
+	/// Returns
+	/// - While(<expr>, <block>)
 	/// 
-	fn parse_loop(&mut self) -> Result<(), ParserError> {
-		let Some(Token::While) = self.input.next() else {
-			return Err(ParserError::UnexpectedToken)
-		};
-
-		self.nodes.push(Node {
-			variant: NodeType::StmtWhile,
-			parent: self.blocks.last().copied()
-		});
-		let index = self.nodes.len() - 1;
+	fn parse_loop(&mut self) -> Result<NodeId, ParserError<'a>> {
+		self.expect(Token::While)?;
+		let span = self.span;
 
-		self.parse_expression()?;
-		self.nodes.last_mut().unwrap().parent = Some(index);
+		let condition = self.parse_expression()?;
+		let body = self.parse_block()?;
 
-		self.parse_block()?;
-		self.nodes.last_mut().unwrap().parent = Some(index);
-
-		Ok(())
+		Ok(self.add_node(NodeKind::While, &[condition, body], span))
 	}
 
-	/// Parses reassignment expression
+	/// Parses reassignment expression, or a function call statement
 	/// 
 	/// Expects:
-	/// <ident> = <expr>
+	/// - <ident> = <expr>
+	/// - <ident> ( <expr>[0+, comma separated] )
 	/// 
 	/// Returns
-	/// - <StmtAssign>
-	/// - <Expr>
-	fn parse_reassignment(&mut self) -> Result<(), ParserError> {
+	/// - Assign(<ident>, <expr>)
+	/// - Expr(<call>)
+	fn parse_reassignment(&mut self) -> Result<NodeId, ParserError<'a>> {
 		/* let <Ident> = <expr> */ 
 
-		let ident_name = match self.input.next() {
-			Some(Token::Ident(ident)) => ident,
-			Some(_) => return Err(ParserError::UnexpectedToken),
-			None => return Err(ParserError::EndOfInput)
-		};
+		let ident_name = self.expect_ident()?;
+		let span = self.span;
 
-		match self.input.next() {
-			Some(Token::Equal) => (),
-			Some(_) => return Err(ParserError::UnexpectedToken),
-			None => return Err(ParserError::EndOfInput)
-		};
+		if let Some(Token::LParen) = self.peek_token() {
+			let call = self.parse_call(ident_name, span)?;
 
-		self.nodes.push(Node {
-			variant: NodeType::StmtAssign(ident_name.to_string()),
-			parent: self.blocks.last().copied()
-		});
-		let index = self.nodes.len() - 1;
+			self.expect_end_of_statement()?;
+			return Ok(self.add_node(NodeKind::Expr, &[call], span));
+		}
 
-		self.parse_expression()?;
-		self.nodes.last_mut().unwrap().parent = Some(index);
+		self.expect(Token::Equal)?;
 
-		Ok(())
+		let value = self.parse_expression()?;
+
+		self.expect_end_of_statement()?;
+		Ok(self.add_node(NodeKind::Assign(ident_name), &[value], span))
 	}
 
-	fn parse_expression(&mut self) -> Result<(), ParserError> {
-		let mut operators: Vec<NodeType> = Vec::new();
-
-		#[inline(always)]
-		fn precedence(node_type: &NodeType) -> usize {
-			match node_type {
-				NodeType::ExprLess => 3,
-				NodeType::ExprGreaterEqual => 3,
-				NodeType::ExprLessEqual => 3,
-				NodeType::ExprGreater => 3,
-				NodeType::ExprNotEqual => 3,
-				NodeType::ExprEqual => 3,
-				NodeType::ExprBinDiv => 2,
-				NodeType::ExprBinMul => 2,
-				NodeType::ExprBinAdd => 1,
-				NodeType::ExprBinSub => 1,
-				_ => 0,
-			}
-		}	
-
-		while let Some(token) = self.input.peek() {
-			let parent = self.blocks.last().copied();
-			let variant = match token {
-				Token::Ident(name) => NodeType::ExprIdent(name.to_string()),
-				Token::IntLiteral(value) => NodeType::ExprLiteral(*value),
-				Token::LParen => NodeType::ExprParen,
-				Token::RParen => NodeType::ExprParen,
-				Token::Plus => NodeType::ExprBinAdd,
-				Token::Minus => NodeType::ExprBinSub,
-				Token::Star => NodeType::ExprBinMul,
-				Token::FSlash => NodeType::ExprBinDiv,
-				Token::NotEqual => NodeType::ExprNotEqual,
-				Token::EqualEqual => NodeType::ExprEqual,
-				Token::Greater => NodeType::ExprGreater,
-				Token::LessEqual => NodeType::ExprLessEqual,
-				Token::Less => NodeType::ExprLess,
-				Token::GreaterEqual => NodeType::ExprGreaterEqual,
-				_ => break,
-			};
-			
-			match variant {
-				NodeType::ExprParen
-				=> {
-					if let Token::LParen = token {
-						operators.push(NodeType::ExprParen);
-					} else {
-						while let Some(stack_variant) = operators.pop() {
-							if let NodeType::ExprParen = stack_variant {
-								break;
+	/// Parses an expression tree using the shunting-yard algorithm
+	/// 
+	/// The expression ends at the first token that can't continue it
+	fn parse_expression(&mut self) -> Result<NodeId, ParserError<'a>> {
+		let mut operators: Vec<(Operator, Span)> = Vec::new();
+		let mut operands: Vec<NodeId> = Vec::new();
+		// Operands and operators must alternate
+		let mut expect_operand = true;
+
+		while let Some(SpannedToken { token, span }) = self.input.peek() {
+			let (token, span) = (token.clone(), *span);
+
+			match token {
+				Token::Ident(name) => {
+					if !expect_operand {
+						break;
+					}
+					expect_operand = false;
+					self.next_token();
+
+					let operand = match self.peek_token() {
+						Some(Token::LParen) => self.parse_call(name.into_owned(), span)?,
+						_ => self.add_node(NodeKind::Ident(name.into_owned()), &[], span)
+					};
+					operands.push(operand);
+					continue;
+				},
+				Token::IntLiteral(value) => {
+					if !expect_operand {
+						break;
+					}
+					expect_operand = false;
+
+					// The smallest integer can only be written negated, which makes it one literal
+					let (value, span) = match i64::try_from(value) {
+						Ok(value) => (value, span),
+						Err(_) => match operators.last() {
+							Some(&(Operator::Unary(UnaryOp::Neg), neg_span)) if value == i64::MIN.unsigned_abs() => {
+								operators.pop();
+								let len = span.offset + span.len - neg_span.offset;
+								(i64::MIN, Span { len, ..neg_span })
+							},
+							_ => {
+								self.errors.push(ParserError::LiteralOutOfRange(span));
+								(value as i64, span)
 							}
-							self.nodes.push(Node {
-								variant: stack_variant,
-								parent
-							});
 						}
-						self.nodes.push(Node {
-							variant,
-							parent
-						});
-					}	
+					};
+					operands.push(self.add_node(NodeKind::Literal(value), &[], span));
 				},
-				NodeType::ExprIdent(_) | NodeType::ExprLiteral(_)
-				=> {
-					self.nodes.push(Node {
-						variant,
-						parent
-					});	
+				Token::LParen => {
+					if !expect_operand {
+						break;
+					}
+					operators.push((Operator::Paren, span));
 				},
-				NodeType::ExprBinAdd | NodeType::ExprBinSub |
-				NodeType::ExprBinMul | NodeType::ExprBinDiv |
-				NodeType::ExprEqual  | NodeType::ExprGreater|
-				NodeType::ExprLessEqual | NodeType::ExprNotEqual|
-				NodeType::ExprLess | NodeType::ExprGreaterEqual
-				=> {
-					while let Some(stack_variant) = operators.pop() {
-						if precedence(&variant) > precedence(&stack_variant) {
-							operators.push(stack_variant);
+				Token::RParen => {
+					if expect_operand {
+						return Err(self.unexpected("an expression"));
+					}
+					if !operators.iter().any(|(operator, _)| matches!(operator, Operator::Paren)) {
+						// Unmatched, so belongs to the surrounding code
+						break;
+					}
+					while let Some((operator, operator_span)) = operators.pop() {
+						if let Operator::Paren = operator {
 							break;
 						}
-						self.nodes.push(Node {
-							variant: stack_variant,
-							parent
-						});
+						self.apply_operator(&mut operands, operator, operator_span);
 					}
-					operators.push(variant);		
 				},
-				_ => ()
+				// Unary operators bind tighter than any binary operator
+				Token::Minus if expect_operand => {
+					operators.push((Operator::Unary(UnaryOp::Neg), span));
+				},
+				Token::Not => {
+					if !expect_operand {
+						break;
+					}
+					operators.push((Operator::Unary(UnaryOp::Not), span));
+				},
+				token => {
+					let op = match token {
+						Token::Plus => BinaryOp::Add,
+						Token::Minus => BinaryOp::Sub,
+						Token::Star => BinaryOp::Mul,
+						Token::FSlash => BinaryOp::Div,
+						Token::EqualEqual => BinaryOp::Equal,
+						Token::NotEqual => BinaryOp::NotEqual,
+						Token::Greater => BinaryOp::Greater,
+						Token::GreaterEqual => BinaryOp::GreaterEqual,
+						Token::Less => BinaryOp::Less,
+						Token::LessEqual => BinaryOp::LessEqual,
+						Token::AndAnd => BinaryOp::And,
+						Token::OrOr => BinaryOp::Or,
+						_ => break
+					};
+					if expect_operand {
+						return Err(self.unexpected("an expression"));
+					}
+					expect_operand = true;
+
+					let operator = Operator::Binary(op);
+					while let Some((stack_operator, stack_span)) = operators.pop() {
+						if operator.precedence() > stack_operator.precedence() {
+							operators.push((stack_operator, stack_span));
+							break;
+						}
+						self.apply_operator(&mut operands, stack_operator, stack_span);
+					}
+
+					operators.push((operator, span));
+				}
 			}
 
-			self.input.next();
+			self.next_token();
 		}
 
-		while let Some(variant) = operators.pop() {
-			self.nodes.push(Node {
-				variant,
-				parent: self.blocks.last().copied()
-			});
+		if expect_operand {
+			return Err(self.unexpected("an expression"));
 		}
 
-		Ok(())
+		while let Some((operator, span)) = operators.pop() {
+			if let Operator::Paren = operator {
+				return Err(self.unexpected("`)`"));
+			}
+			self.apply_operator(&mut operands, operator, span);
+		}
+
+		Ok(operands.pop().expect("Expression has no operand"))
+	}
+
+	/// Replaces the operands of an operator with a node applying it
+	fn apply_operator(&mut self, operands: &mut Vec<NodeId>, operator: Operator, span: Span) {
+		let (kind, arity) = match operator {
+			Operator::Unary(op) => (NodeKind::Unary(op), 1),
+			Operator::Binary(op) => (NodeKind::Binary(op), 2),
+			Operator::Paren => unreachable!("Attempted to apply a parenthesis")
+		};
+		let start = operands.len().checked_sub(arity).expect("Operator is missing an operand");
+		let children = operands.split_off(start);
+		let node = self.add_node(kind, &children, span);
+		operands.push(node);
+	}
+
+	/// Parses the arguments of a function call, after its name
+	/// 
+	/// Expects:
+	/// ( <expr>[0+, comma separated] )
+	/// 
+	/// Returns:
+	/// Call(<expr>[0+])
+	fn parse_call(&mut self, name: String, span: Span) -> Result<NodeId, ParserError<'a>> {
+		self.expect(Token::LParen)?;
+
+		let mut arguments = Vec::new();
+		if self.next_token_if_eq(&Token::RParen).is_none() {
+			loop {
+				arguments.push(self.parse_expression()?);
+				if self.next_token_if_eq(&Token::Comma).is_some() {
+					continue;
+				}
+				self.expect(Token::RParen)?;
+				break;
+			}
+		}
+
+		Ok(self.add_node(NodeKind::Call(name), &arguments, span))
 	}
 }
@@ -0,0 +1,128 @@
+//! Peephole optimisation of generated assembly
+//!
+//! Points jumps that land on another jump at its target, matches short runs of adjacent lines and
+//! removes those that do nothing useful, then drops local labels nothing jumps to, until none of
+//! these change anything.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::asm::{Line, Operand};
+
+/// Jumps to a label, which have no other effect and so may be removed when they go to the very next instruction
+const JUMPS: [&str; 7] = ["jmp", "je", "jne", "jg", "jge", "jl", "jle"];
+
+pub fn optimise(lines: &mut Vec<Line>) {
+	loop {
+		let threaded = thread_jumps(lines);
+		let rewritten = rewrite_windows(lines);
+		if !remove_unreferenced_labels(lines) && !rewritten && !threaded {
+			break;
+		}
+	}
+}
+
+/// Points jumps to a label whose first instruction is `jmp` straight at where that jumps to
+fn thread_jumps(lines: &mut [Line]) -> bool {
+	// Labels directly followed by an unconditional jump, and its target
+	let mut forwards = HashMap::new();
+	for (index, line) in lines.iter().enumerate() {
+		let Line::Label(label) = line else {
+			continue;
+		};
+		let next = lines[index + 1..].iter().find(|line| !matches!(line, Line::Label(_)));
+		if let Some(Line::Instr("jmp", target)) = next {
+			if let [Operand::Label(target)] = &target[..] {
+				forwards.insert(label.clone(), target.clone());
+			}
+		}
+	}
+
+	let mut changed = false;
+	for line in lines.iter_mut() {
+		let Line::Instr(jump, operands) = line else {
+			continue;
+		};
+		let [Operand::Label(target)] = &mut operands[..] else {
+			continue;
+		};
+		if !JUMPS.contains(jump) {
+			continue;
+		}
+		// Follow the chain to its end, leaving jumps that only lead round in a loop alone
+		let mut end = target.clone();
+		let mut seen = HashSet::new();
+		while let Some(next) = forwards.get(&end) {
+			if !seen.insert(end.clone()) {
+				break;
+			}
+			end = next.clone();
+		}
+		if !forwards.contains_key(&end) && end != *target {
+			*target = end;
+			changed = true;
+		}
+	}
+	changed
+}
+
+fn rewrite_windows(lines: &mut Vec<Line>) -> bool {
+	let mut output = Vec::with_capacity(lines.len());
+	let mut changed = false;
+	let mut index = 0;
+	while index < lines.len() {
+		match rewrite(&lines[index..]) {
+			Some((replaced, replacement)) => {
+				output.extend(replacement);
+				index += replaced;
+				changed = true;
+			},
+			None => {
+				output.push(lines[index].clone());
+				index += 1;
+			}
+		}
+	}
+	*lines = output;
+	changed
+}
+
+/// Rewrites the lines at the start of a window, returning how many are replaced and what with
+fn rewrite(window: &[Line]) -> Option<(usize, Vec<Line>)> {
+	match window {
+		[Line::Instr("mov", operands), ..] if operands[0] == operands[1] => Some((1, Vec::new())),
+		// Copying a value straight back, as when a result is stored and then returned or reloaded
+		[first @ Line::Instr("mov", moved), Line::Instr("mov", back), ..] if moved[0] == back[1] && moved[1] == back[0] => {
+			Some((2, vec![first.clone()]))
+		},
+		// A register written just before it is restored on the way out of a function
+		[Line::Instr("mov", moved), pop @ Line::Instr("pop", popped), ..] if moved[0] == popped[0] && !moved[0].is_memory() => {
+			Some((2, vec![pop.clone()]))
+		},
+		[Line::Instr(jump, target), rest @ ..] if JUMPS.contains(jump) => {
+			let to_next = rest.iter()
+				.map_while(|line| match line {
+					Line::Label(label) => Some(label),
+					_ => None
+				})
+				.any(|label| target[..] == [Operand::Label(label.clone())]);
+			to_next.then(|| (1, Vec::new()))
+		},
+		_ => None
+	}
+}
+
+/// Removes local labels, those starting with `.`, that no line refers to
+///
+/// Other labels are entry points and always kept
+fn remove_unreferenced_labels(lines: &mut Vec<Line>) -> bool {
+	let referenced: HashSet<String> = lines.iter()
+		.flat_map(Line::references)
+		.map(str::to_string)
+		.collect();
+	let count = lines.len();
+	lines.retain(|line| match line {
+		Line::Label(label) => !label.starts_with('.') || referenced.contains(label),
+		_ => true
+	});
+	lines.len() != count
+}
@@ -0,0 +1,448 @@
+//! Static single assignment form, where every virtual register is written by exactly one instruction
+//!
+//! Registers written in several places are split into one register per write, joined by phis
+//! where control flow merges. Phis are turned back into copies before generating assembly.
+
+use std::collections::{HashMap, HashSet};
+use std::mem;
+
+use crate::ir::{Block, BlockId, Function, Instr, Operand, Terminator, VReg};
+
+/// Dominator tree of a function, where a block dominates another if every path from the entry to it passes through the first
+pub struct Dominators {
+	/// Immediate dominator of each block, the entry has none
+	idom: Vec<Option<BlockId>>,
+	/// Blocks in reverse postorder, so each comes before the blocks it dominates
+	order: Vec<BlockId>
+}
+
+impl Dominators {
+	/// Finds dominators with the iterative algorithm by Cooper, Harvey and Kennedy
+	///
+	/// Every block must be reachable from the entry
+	pub fn new(function: &Function) -> Dominators {
+		let order = reverse_postorder(function);
+		let mut rank = vec![0; function.blocks.len()];
+		for (index, block) in order.iter().enumerate() {
+			rank[block.0] = index;
+		}
+		let predecessors = function.predecessors();
+
+		let mut idom: Vec<Option<BlockId>> = vec![None; function.blocks.len()];
+		idom[0] = Some(BlockId(0));
+		let mut changed = true;
+		while changed {
+			changed = false;
+			for &block in &order[1..] {
+				let mut processed = predecessors[block.0].iter().filter(|pred| idom[pred.0].is_some());
+				let Some(&first) = processed.next() else { continue };
+				let new_idom = processed.fold(first, |mut a, &b| {
+					let mut b = b;
+					// Walks both up the tree until they meet
+					while a != b {
+						while rank[a.0] > rank[b.0] {
+							a = idom[a.0].unwrap();
+						}
+						while rank[b.0] > rank[a.0] {
+							b = idom[b.0].unwrap();
+						}
+					}
+					a
+				});
+				if idom[block.0] != Some(new_idom) {
+					idom[block.0] = Some(new_idom);
+					changed = true;
+				}
+			}
+		}
+		idom[0] = None;
+
+		Dominators { idom, order }
+	}
+
+	pub fn idom(&self, block: BlockId) -> Option<BlockId> {
+		self.idom[block.0]
+	}
+
+	pub fn dominates(&self, a: BlockId, mut b: BlockId) -> bool {
+		loop {
+			if a == b {
+				return true;
+			}
+			match self.idom[b.0] {
+				Some(idom) => b = idom,
+				None => return false
+			}
+		}
+	}
+
+	/// Blocks in reverse postorder, so each comes after its dominators
+	pub fn order(&self) -> &[BlockId] {
+		&self.order
+	}
+
+	/// Blocks immediately dominated by each block
+	pub fn children(&self) -> Vec<Vec<BlockId>> {
+		let mut children = vec![Vec::new(); self.idom.len()];
+		for &block in &self.order {
+			if let Some(idom) = self.idom[block.0] {
+				children[idom.0].push(block);
+			}
+		}
+		children
+	}
+
+	/// Dominance frontier of each block, the blocks where its dominance ends
+	pub fn frontiers(&self, function: &Function) -> Vec<Vec<BlockId>> {
+		let mut frontiers = vec![Vec::new(); function.blocks.len()];
+		for (block, predecessors) in function.predecessors().iter().enumerate() {
+			if predecessors.len() < 2 {
+				continue;
+			}
+			let idom = self.idom[block];
+			for &pred in predecessors {
+				let mut runner = Some(pred);
+				while runner.is_some() && runner != idom {
+					let current = runner.unwrap();
+					if !frontiers[current.0].contains(&BlockId(block)) {
+						frontiers[current.0].push(BlockId(block));
+					}
+					runner = self.idom[current.0];
+				}
+			}
+		}
+		frontiers
+	}
+}
+
+fn reverse_postorder(function: &Function) -> Vec<BlockId> {
+	let mut visited = vec![false; function.blocks.len()];
+	let mut postorder = Vec::with_capacity(function.blocks.len());
+	// Blocks with the index of the next successor to visit
+	let mut stack = vec![(BlockId(0), 0)];
+	visited[0] = true;
+	while let Some((block, next)) = stack.pop() {
+		let successors = function.blocks[block.0].terminator.successors();
+		match successors.get(next) {
+			Some(&successor) => {
+				stack.push((block, next + 1));
+				if !visited[successor.0] {
+					visited[successor.0] = true;
+					stack.push((successor, 0));
+				}
+			},
+			None => postorder.push(block)
+		}
+	}
+	postorder.reverse();
+	postorder
+}
+
+/// Converts a function into SSA form, placing phis at the iterated dominance frontiers of each write
+///
+/// Phis that turn out not to be needed are left for dead code elimination
+pub fn construct(function: &mut Function) {
+	// Phis in the entry would have no value for when the function is entered
+	if !function.predecessors()[0].is_empty() {
+		function.insert_block(0, Block { instrs: Vec::new(), terminator: Terminator::Jump(BlockId(0)) });
+	}
+
+	let dominators = Dominators::new(function);
+	let frontiers = dominators.frontiers(function);
+
+	let mut writes = vec![0; function.reg_count];
+	let mut def_blocks: Vec<Vec<BlockId>> = vec![Vec::new(); function.reg_count];
+	for param in &function.params {
+		writes[param.0] += 1;
+		def_blocks[param.0].push(BlockId(0));
+	}
+	for (index, block) in function.blocks.iter().enumerate() {
+		for instr in &block.instrs {
+			writes[instr.dest().0] += 1;
+			if !def_blocks[instr.dest().0].contains(&BlockId(index)) {
+				def_blocks[instr.dest().0].push(BlockId(index));
+			}
+		}
+	}
+
+	// Original register of each phi at the start of each block
+	let mut phis: Vec<Vec<VReg>> = vec![Vec::new(); function.blocks.len()];
+	for reg in (0..function.reg_count).filter(|reg| writes[*reg] > 1) {
+		let mut worklist = def_blocks[reg].clone();
+		while let Some(block) = worklist.pop() {
+			for &frontier in &frontiers[block.0] {
+				if !phis[frontier.0].contains(&VReg(reg)) {
+					phis[frontier.0].push(VReg(reg));
+					if !def_blocks[reg].contains(&frontier) {
+						worklist.push(frontier);
+					}
+				}
+			}
+		}
+	}
+	for (block, regs) in function.blocks.iter_mut().zip(&phis) {
+		let new_phis = regs.iter().map(|reg| Instr::Phi { dest: *reg, incoming: Vec::new() });
+		block.instrs.splice(0..0, new_phis);
+	}
+
+	let mut renamer = Renamer {
+		renamed: writes.iter().map(|writes| *writes > 1).collect(),
+		stacks: vec![Vec::new(); function.reg_count],
+		phis,
+		children: dominators.children()
+	};
+	for param in &function.params {
+		renamer.stacks[param.0].push(Operand::Reg(*param));
+	}
+	renamer.rename(function, BlockId(0));
+}
+
+struct Renamer {
+	/// Whether each original register is written more than once, so needs new names
+	renamed: Vec<bool>,
+	/// Current name of each original register
+	stacks: Vec<Vec<Operand>>,
+	phis: Vec<Vec<VReg>>,
+	children: Vec<Vec<BlockId>>
+}
+
+impl Renamer {
+	fn current(&self, operand: Operand) -> Operand {
+		match operand {
+			Operand::Reg(reg) if self.renamed[reg.0] => {
+				// Only read on paths where it was never written, so any value will do
+				self.stacks[reg.0].last().copied().unwrap_or(Operand::Const(0))
+			},
+			operand => operand
+		}
+	}
+
+	/// Renames the writes in a block and the blocks it dominates, and the reads they reach
+	fn rename(&mut self, function: &mut Function, block: BlockId) {
+		let mut pushed = Vec::new();
+		let phi_count = self.phis[block.0].len();
+		let mut reg_count = function.reg_count;
+
+		for (index, instr) in function.blocks[block.0].instrs.iter_mut().enumerate() {
+			if index >= phi_count {
+				for operand in instr.operands_mut() {
+					*operand = self.current(*operand);
+				}
+			}
+			let original = instr.dest();
+			if self.renamed[original.0] {
+				let reg = VReg(reg_count);
+				reg_count += 1;
+				*instr.dest_mut() = reg;
+				self.stacks[original.0].push(Operand::Reg(reg));
+				pushed.push(original);
+			}
+		}
+		function.reg_count = reg_count;
+		if let Some(operand) = function.blocks[block.0].terminator.operand_mut() {
+			*operand = self.current(*operand);
+		}
+
+		for successor in function.blocks[block.0].terminator.successors() {
+			for (index, original) in self.phis[successor.0].clone().into_iter().enumerate() {
+				let value = self.current(Operand::Reg(original));
+				if let Instr::Phi { incoming, .. } = &mut function.blocks[successor.0].instrs[index] {
+					incoming.push((block, value));
+				}
+			}
+		}
+
+		for child in self.children[block.0].clone() {
+			self.rename(function, child);
+		}
+		for original in pushed {
+			self.stacks[original.0].pop();
+		}
+	}
+}
+
+/// Pairs of registers that are live at the same time somewhere in an SSA function
+///
+/// Phi operands are live at the end of the predecessor they come from, and phis all write at the start of their block
+fn interference(function: &Function) -> HashSet<(VReg, VReg)> {
+	let blocks = &function.blocks;
+	let live_out = |live_in: &[HashSet<VReg>], block: usize| -> HashSet<VReg> {
+		let mut live = HashSet::new();
+		for successor in blocks[block].terminator.successors() {
+			let phi_dests: Vec<VReg> = blocks[successor.0].phis().map(Instr::dest).collect();
+			live.extend(live_in[successor.0].iter().filter(|reg| !phi_dests.contains(reg)));
+			for phi in blocks[successor.0].phis() {
+				if let Instr::Phi { incoming, .. } = phi {
+					live.extend(incoming.iter().filter(|(pred, _)| pred.0 == block).filter_map(|(_, operand)| operand.reg()));
+				}
+			}
+		}
+		live
+	};
+
+	let mut edges = HashSet::new();
+	let mut live_in: Vec<HashSet<VReg>> = vec![HashSet::new(); blocks.len()];
+	// The last pass, once nothing changes, records the interference
+	let mut changed = true;
+	let mut recording = false;
+	while changed || !recording {
+		recording = !changed;
+		changed = false;
+		for index in (0..blocks.len()).rev() {
+			let mut live = live_out(&live_in, index);
+			live.extend(blocks[index].terminator.operand().and_then(Operand::reg));
+			let mut phi_dests = Vec::new();
+			for instr in blocks[index].instrs.iter().rev() {
+				let dest = instr.dest();
+				live.remove(&dest);
+				if let Instr::Phi { .. } = instr {
+					phi_dests.push(dest);
+					continue;
+				}
+				if recording {
+					for other in &live {
+						edges.insert((dest, *other));
+						edges.insert((*other, dest));
+					}
+				}
+				live.extend(instr.operands().into_iter().filter_map(Operand::reg));
+			}
+			if index == 0 {
+				phi_dests.extend(&function.params);
+			}
+
+			// Phis and parameters are written together at the start, and interfere with each other if both are live
+			if recording {
+				let mut written = live.clone();
+				for dest in &phi_dests {
+					for other in written.iter().filter(|other| *other != dest) {
+						edges.insert((*dest, *other));
+						edges.insert((*other, *dest));
+					}
+					written.insert(*dest);
+				}
+			}
+			for dest in &phi_dests {
+				live.remove(dest);
+			}
+			if live != live_in[index] {
+				live_in[index] = live;
+				changed = true;
+			}
+		}
+	}
+	edges
+}
+
+/// Replaces phis with copies, leaving a function that is no longer in SSA form
+///
+/// Registers joined by a phi share a name where their live ranges don't overlap, so most phis need no copies.
+/// The rest copy at the end of each predecessor, on a new block if the predecessor has other successors.
+pub fn destruct(function: &mut Function) {
+	if !function.blocks.iter().any(|block| block.phis().next().is_some()) {
+		return;
+	}
+
+	// Coalesces registers joined by phis into classes that are never live at the same time
+	let interference = interference(function);
+	let mut class: Vec<usize> = (0..function.reg_count).collect();
+	let mut members: Vec<Vec<VReg>> = (0..function.reg_count).map(|reg| vec![VReg(reg)]).collect();
+	for block in &function.blocks {
+		for phi in block.phis() {
+			let Instr::Phi { dest, incoming } = phi else { continue };
+			for (_, operand) in incoming {
+				let Operand::Reg(reg) = operand else { continue };
+				let (a, b) = (class[dest.0], class[reg.0]);
+				if a == b {
+					continue;
+				}
+				let interferes = members[a].iter()
+					.any(|x| members[b].iter().any(|y| interference.contains(&(*x, *y))));
+				if !interferes {
+					for member in mem::take(&mut members[b]) {
+						class[member.0] = a;
+						members[a].push(member);
+					}
+				}
+			}
+		}
+	}
+	let replacements: HashMap<VReg, Operand> = (0..function.reg_count)
+		.filter(|reg| class[*reg] != *reg)
+		.map(|reg| (VReg(reg), Operand::Reg(VReg(class[reg]))))
+		.collect();
+	function.replace_uses(&replacements);
+	for block in &mut function.blocks {
+		for instr in &mut block.instrs {
+			let dest = instr.dest();
+			*instr.dest_mut() = VReg(class[dest.0]);
+		}
+	}
+	for param in &mut function.params {
+		*param = VReg(class[param.0]);
+	}
+
+	// Copies still needed on each edge, as (predecessor, block) and the copies
+	let mut edge_copies: Vec<((BlockId, BlockId), ParallelCopy)> = Vec::new();
+	for (index, block) in function.blocks.iter_mut().enumerate() {
+		let phi_count = block.phis().count();
+		for phi in block.instrs.drain(..phi_count) {
+			let Instr::Phi { dest, incoming } = phi else { unreachable!() };
+			for (pred, operand) in incoming {
+				if operand == Operand::Reg(dest) {
+					continue;
+				}
+				let edge = (pred, BlockId(index));
+				match edge_copies.iter_mut().find(|(existing, _)| *existing == edge) {
+					Some((_, copies)) => copies.push((dest, operand)),
+					None => edge_copies.push((edge, vec![(dest, operand)]))
+				}
+			}
+		}
+	}
+
+	for ((pred, block), copies) in edge_copies {
+		let copies = sequentialise(function, copies);
+		if function.blocks[pred.0].terminator.successors().len() == 1 {
+			function.blocks[pred.0].instrs.extend(copies);
+			continue;
+		}
+		// The copies can't go at the end of a predecessor that branches elsewhere too
+		let split = BlockId(function.blocks.len());
+		function.blocks.push(Block { instrs: copies, terminator: Terminator::Jump(block) });
+		for successor in function.blocks[pred.0].terminator.successors_mut() {
+			if *successor == block {
+				*successor = split;
+			}
+		}
+	}
+}
+
+/// Copies into registers that happen at once, as each reads the values from before any of them
+type ParallelCopy = Vec<(VReg, Operand)>;
+
+/// Orders copies that happen at once so none overwrites a register another still has to read,
+/// breaking cycles through a new register
+fn sequentialise(function: &mut Function, mut copies: ParallelCopy) -> Vec<Instr> {
+	let mut instrs = Vec::new();
+	while !copies.is_empty() {
+		let ready = copies.iter().position(|(dest, _)| !copies.iter().any(|(_, src)| *src == Operand::Reg(*dest)));
+		match ready {
+			Some(index) => {
+				let (dest, src) = copies.remove(index);
+				instrs.push(Instr::Copy { dest, src });
+			},
+			None => {
+				let blocked = copies[0].0;
+				let temp = function.new_reg();
+				instrs.push(Instr::Copy { dest: temp, src: Operand::Reg(blocked) });
+				for (_, src) in &mut copies {
+					if *src == Operand::Reg(blocked) {
+						*src = Operand::Reg(temp);
+					}
+				}
+			}
+		}
+	}
+	instrs
+}
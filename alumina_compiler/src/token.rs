@@ -1,21 +1,27 @@
+use std::borrow::Cow;
 use std::io;
-use std::iter::Peekable;
-use std::sync::Arc;
+use std::marker::PhantomData;
 
-use char_reader::CharReader;
+use char_reader::{CharReader, CharReaderError, Position, SourceText, StrReader};
 
+/// A token whose text is borrowed from the source when it is in memory, and owned when it is streamed
 #[derive(Debug, Clone, PartialEq)]
-pub enum Token {
+pub enum Token<'a> {
     Sep,
     Exit,
     Let,
     If,
     Else,
     While,
-    Ident(Arc<str>),
-    IntLiteral(u32),
+    Fn,
+    Return,
+    Ident(Cow<'a, str>),
+    /// The magnitude of an integer, which the parser checks is in range
+    IntLiteral(u64),
     Not,
     NotEqual,
+    AndAnd,
+    OrOr,
     Equal,
     EqualEqual,
     Greater,
@@ -29,45 +35,153 @@ pub enum Token {
     LParen,
     RParen,
     LBrace,
-    RBrace
+    RBrace,
+    Comma,
+    /// Trivia, only produced when enabled in [`LexerOptions`]
+    LineComment(Cow<'a, str>),
+    /// Trivia, only produced when enabled in [`LexerOptions`]
+    BlockComment(Cow<'a, str>)
+}
+
+/// Location of a token within the source text
+///
+/// Lines and columns are 1-indexed, offset and length are in bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+    pub len: usize,
+}
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpannedToken<'a> {
+    pub token: Token<'a>,
+    pub span: Span,
+}
+
+impl std::fmt::Display for Token<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Sep => write!(f, "end of statement"),
+            Token::Exit => write!(f, "exit"),
+            Token::Let => write!(f, "let"),
+            Token::If => write!(f, "if"),
+            Token::Else => write!(f, "else"),
+            Token::While => write!(f, "while"),
+            Token::Fn => write!(f, "fn"),
+            Token::Return => write!(f, "return"),
+            Token::Ident(name) => write!(f, "{name}"),
+            Token::IntLiteral(value) => write!(f, "{value}"),
+            Token::Not => write!(f, "!"),
+            Token::NotEqual => write!(f, "!="),
+            Token::AndAnd => write!(f, "&&"),
+            Token::OrOr => write!(f, "||"),
+            Token::Equal => write!(f, "="),
+            Token::EqualEqual => write!(f, "=="),
+            Token::Greater => write!(f, ">"),
+            Token::GreaterEqual => write!(f, ">="),
+            Token::Less => write!(f, "<"),
+            Token::LessEqual => write!(f, "<="),
+            Token::Plus => write!(f, "+"),
+            Token::Minus => write!(f, "-"),
+            Token::Star => write!(f, "*"),
+            Token::FSlash => write!(f, "/"),
+            Token::LParen => write!(f, "("),
+            Token::RParen => write!(f, ")"),
+            Token::LBrace => write!(f, "{{"),
+            Token::RBrace => write!(f, "}}"),
+            Token::Comma => write!(f, ","),
+            Token::LineComment(text) => write!(f, "//{text}"),
+            Token::BlockComment(text) => write!(f, "/*{text}*/"),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum LexerError {
-    IntParse(std::num::ParseIntError),
+    IntParse(std::num::ParseIntError, Span),
     IOError(io::Error),
-    UnexpectedCharacter(char),
+    /// A byte sequence that isn't valid UTF-8, spanning its first byte
+    InvalidUtf8(Span),
+    UnexpectedCharacter(char, Span),
+    /// Block comment still open at the end of input, spanning its opening `/*`
+    UnterminatedComment(Span),
     EndOfInput
 }
-impl From<std::num::ParseIntError> for LexerError {
-    fn from(err: std::num::ParseIntError) -> LexerError {
-        LexerError::IntParse(err)
-    }
-}
 impl From<io::Error> for LexerError {
     fn from(err: io::Error) -> Self {
         LexerError::IOError(err)
     }
 }
 
-pub struct Lexer<R: io::Read> {
-    input: Peekable<CharReader<R>>,
+const KEYWORDS: [(&str, Token<'static>); 7] = [
+    ("exit", Token::Exit),
+    ("let", Token::Let),
+    ("if", Token::If),
+    ("else", Token::Else),
+    ("while", Token::While),
+    ("fn", Token::Fn),
+    ("return", Token::Return),
+];
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LexerOptions {
+    /// Emit comments as tokens instead of skipping them
+    pub trivia: bool,
 }
 
-impl <R: io::Read>Lexer<R> {
+pub struct Lexer<'a, S: SourceText<'a>> {
+    input: S,
+    /// Lifetime of text borrowed from the source into tokens
+    source: PhantomData<&'a str>,
+    options: LexerOptions,
+    /// Position of the first character of the current token
+    start: Position,
+}
+
+impl <R: io::Read>Lexer<'static, CharReader<R>> {
     
-    pub fn new(reader: R) -> Lexer<R> {
-        let input = CharReader::new(reader).peekable();
-        Lexer { input }
+    pub fn new(reader: R) -> Lexer<'static, CharReader<R>> {
+        Lexer::with_options(reader, LexerOptions::default())
     }
 
-    pub fn tokenize(reader: R) -> Result<Vec<Token>, LexerError> {
+    pub fn with_options(reader: R, options: LexerOptions) -> Lexer<'static, CharReader<R>> {
+        Lexer::from_source(CharReader::new(reader), options)
+    }
 
-        let mut lexer = Lexer::new(reader);
+    /// Lexes a stream, copying the text of identifiers and comments into each token
+    pub fn tokenize(reader: R) -> Result<Vec<SpannedToken<'static>>, LexerError> {
+        Lexer::new(reader).collect_tokens()
+    }
+}
+
+impl <'a>Lexer<'a, StrReader<'a>> {
+    /// Lexes source that is already in memory, with identifiers and comments borrowing their text from it
+    pub fn from_text(source: &'a str, options: LexerOptions) -> Lexer<'a, StrReader<'a>> {
+        Lexer::from_source(StrReader::new(source), options)
+    }
+
+    pub fn tokenize_str(source: &'a str) -> Result<Vec<SpannedToken<'a>>, LexerError> {
+        Lexer::from_text(source, LexerOptions::default()).collect_tokens()
+    }
+}
+
+impl <'a, S: SourceText<'a>>Lexer<'a, S> {
+    pub fn from_source(input: S, options: LexerOptions) -> Lexer<'a, S> {
+        Lexer { input, source: PhantomData, options, start: Position::default() }
+    }
+
+    fn collect_tokens(mut self) -> Result<Vec<SpannedToken<'a>>, LexerError> {
         let mut tokens = Vec::new();
 
         loop {
-            match lexer.parse_token() {
+            match self.next_token() {
                 Ok(token) => tokens.push(token),
                 Err(LexerError::EndOfInput) => break,
                 Err(err) => return Err(err)
@@ -77,95 +191,205 @@ impl <R: io::Read>Lexer<R> {
         Ok(tokens)
     }
 
+    pub fn next_token(&mut self) -> Result<SpannedToken<'a>, LexerError> {
+        let token = self.parse_token()?;
+        Ok(SpannedToken { token, span: self.current_span() })
+    }
+
+    /// Span covering the characters read since the start of the current token
+    fn current_span(&self) -> Span {
+        Span {
+            offset: self.start.offset,
+            line: self.start.line,
+            column: self.start.column,
+            len: self.input.position().offset - self.start.offset,
+        }
+    }
 
-    fn parse_token(&mut self) -> Result<Token, LexerError> {
-        let token = match self.input.next() {
-            Some('!') => match self.input.next_if_eq(&'=') {
+    /// Reads the next character, or `None` at the end of input
+    fn next_char(&mut self) -> Result<Option<char>, LexerError> {
+        match self.input.next_char() {
+            Ok(ch) => Ok(Some(ch)),
+            Err(CharReaderError::ReachedEOF) => Ok(None),
+            Err(err) => Err(self.reader_error(err))
+        }
+    }
+
+    /// Consumes characters for as long as `predicate` holds, stopping at the end of input
+    fn consume_while(&mut self, predicate: impl FnMut(char) -> bool) -> Result<Cow<'a, str>, LexerError> {
+        self.input.consume_while(predicate).map_err(|err| self.reader_error(err))
+    }
+
+    fn reader_error(&self, err: CharReaderError) -> LexerError {
+        match err {
+            CharReaderError::InvalidUtf8(offset) => {
+                // Invalid bytes don't move the line or column along
+                let Position { line, column, .. } = self.input.position();
+                LexerError::InvalidUtf8(Span { offset, line, column, len: 1 })
+            },
+            CharReaderError::IOError(err) => LexerError::IOError(err),
+            CharReaderError::ReachedEOF => LexerError::EndOfInput,
+            CharReaderError::NoMark => unreachable!("The lexer never goes back to a mark")
+        }
+    }
+
+    /// Reads the next character if it matches, leaving any error to be reported by the next read
+    fn next_char_if(&mut self, func: impl FnOnce(&char) -> bool) -> Result<Option<char>, LexerError> {
+        match self.input.peek_nth(0) {
+            Ok(ch) if func(&ch) => self.next_char(),
+            _ => Ok(None)
+        }
+    }
+
+    fn parse_token(&mut self) -> Result<Token<'a>, LexerError> {
+        // Peeking first skips any byte order mark before the token starts
+        let next = self.input.peek_nth(0);
+        self.start = self.input.position();
+        match next {
+            Ok(ch) if ch.is_numeric() => return self.parse_int(),
+            Ok(ch) if ch.is_alphabetic() => return self.parse_literal(),
+            _ => ()
+        }
+
+        let token = match self.next_char()? {
+            Some('!') => match self.next_char_if(|ch| *ch == '=')? {
                 None => Token::Not,
                 Some(_) => Token::NotEqual
             }
-            Some('=') => match self.input.next_if_eq(&'=') {
+            Some('=') => match self.next_char_if(|ch| *ch == '=')? {
                 None => Token::Equal,
                 Some(_) => Token::EqualEqual
             },
-            Some('>') => match self.input.next_if_eq(&'=') {
+            Some('>') => match self.next_char_if(|ch| *ch == '=')? {
                 None => Token::Greater,
                 Some(_) => Token::GreaterEqual
             },
-            Some('<') => match self.input.next_if_eq(&'=') {
+            Some('<') => match self.next_char_if(|ch| *ch == '=')? {
                 None => Token::Less,
                 Some(_) => Token::LessEqual
             },
+            Some('&') => match self.next_char_if(|ch| *ch == '&')? {
+                None => return Err(LexerError::UnexpectedCharacter('&', self.current_span())),
+                Some(_) => Token::AndAnd
+            },
+            Some('|') => match self.next_char_if(|ch| *ch == '|')? {
+                None => return Err(LexerError::UnexpectedCharacter('|', self.current_span())),
+                Some(_) => Token::OrOr
+            },
             Some('+') => Token::Plus,
             Some('-') => Token::Minus,
             Some('*') => Token::Star,
-            Some('/') => Token::FSlash,
+            Some('/') => match self.next_char_if(|ch| *ch == '/' || *ch == '*')? {
+                None => Token::FSlash,
+                Some('/') => self.parse_line_comment()?,
+                Some(_) => self.parse_block_comment()?
+            },
             Some('(') => Token::LParen,
             Some(')') => Token::RParen,
             Some('{') => Token::LBrace,
             Some('}') => Token::RBrace,
+            Some(',') => Token::Comma,
             Some(';') | Some('\n') => Token::Sep,
-            Some(ch) if ch.is_numeric() => self.parse_int(ch)?,
-            Some(ch) if ch.is_alphabetic() => self.parse_literal(ch)?,
             Some(ch) if ch.is_whitespace() => self.parse_whitespace()?,
-            Some(ch) => return Err(LexerError::UnexpectedCharacter(ch)),
+            Some(ch) => return Err(LexerError::UnexpectedCharacter(ch, self.current_span())),
             None => return Err(LexerError::EndOfInput)
         };
         Ok(token)
     }
 
-    fn parse_whitespace(&mut self) -> Result<Token, LexerError> {
-        loop {
-            match self.input.next_if(|ch| ch.is_whitespace()) {
-                None => break,
-                _ => ()
-            }
-        }
+    /// Skips whitespace, stopping at new lines as they separate statements
+    fn parse_whitespace(&mut self) -> Result<Token<'a>, LexerError> {
+        self.consume_while(|ch| ch.is_whitespace() && ch != '\n')?;
         self.parse_token()
     }
 
-    fn parse_int(&mut self, first_char: char) -> Result<Token, LexerError> {
-        let mut num = first_char.to_string();
-        loop {
-            match self.input.peek() {
-                Some(ch) if ch.is_numeric() => {
-                    num.push(*ch);
-                    self.input.next();
-                },
-                Some(_) | None => break
-            }
-        }
+    /// Parses the remainder of a line comment, leaving the new line in the input
+    fn parse_line_comment(&mut self) -> Result<Token<'a>, LexerError> {
+        let text = self.consume_while(|ch| ch != '\n')?;
 
-        Ok(Token::IntLiteral(num.parse::<u32>()?))
+        if self.options.trivia {
+            Ok(Token::LineComment(text))
+        } else {
+            self.parse_token()
+        }
     }
 
-    fn parse_literal(&mut self, first_char: char) -> Result<Token, LexerError> {
-        let mut literal = first_char.to_string();
+    /// Parses the remainder of a block comment, which may contain nested block comments
+    fn parse_block_comment(&mut self) -> Result<Token<'a>, LexerError> {
+        let opening = Span { len: 2, ..self.current_span() };
+        // Text in memory is borrowed once the comment ends, streamed text is copied as it's read
+        self.input.mark();
+        let mut copied = match self.input.slice_from_mark() {
+            Some(_) => None,
+            None => Some(String::new()),
+        };
+        let mut depth = 1;
+        let mut prev = None;
+
         loop {
-            match self.input.peek() {
-                Some(ch) if ch.is_alphanumeric() => {
-                    literal.push(*ch);
-                    self.input.next();
+            let ch = self.next_char()?.ok_or(LexerError::UnterminatedComment(opening))?;
+            if let Some(copied) = &mut copied {
+                copied.push(ch);
+            }
+            prev = match (prev, ch) {
+                (Some('/'), '*') => {
+                    depth += 1;
+                    None
                 },
-                Some(_) | None => break
+                (Some('*'), '/') => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                    None
+                },
+                _ => Some(ch)
+            };
+        }
+
+        // Leave out the closing `*/`
+        let text = match copied {
+            Some(mut copied) => {
+                copied.truncate(copied.len() - 2);
+                Cow::Owned(copied)
+            },
+            None => {
+                let text = self.input.slice_from_mark().expect("The mark is kept for text in memory");
+                Cow::Borrowed(&text[..text.len() - 2])
             }
+        };
+        self.input.clear_mark();
+
+        if self.options.trivia {
+            Ok(Token::BlockComment(text))
+        } else {
+            self.parse_token()
         }
+    }
+
+    fn parse_int(&mut self) -> Result<Token<'a>, LexerError> {
+        let parsed = self.consume_while(char::is_numeric)?.parse::<u64>();
+        parsed
+            .map(Token::IntLiteral)
+            .map_err(|err| LexerError::IntParse(err, self.current_span()))
+    }
+
+    fn parse_literal(&mut self) -> Result<Token<'a>, LexerError> {
+        let literal = self.consume_while(char::is_alphanumeric)?;
 
-        Ok(match literal.to_lowercase().as_str() {
-            "exit" => Token::Exit,
-            "let" => Token::Let,
-            "if" => Token::If,
-            "else" => Token::Else,
-            "while" => Token::While,
-            _ => Token::Ident(literal.into()),
+        // Keywords are ASCII, so this matches them in any case without lowercasing a copy
+        let keyword = KEYWORDS.iter().find(|(keyword, _)| literal.eq_ignore_ascii_case(keyword));
+        Ok(match keyword {
+            Some((_, token)) => token.clone(),
+            None => Token::Ident(literal),
         })
     }
 
 }
-impl<R: std::io::Read> Iterator for Lexer<R> {
-    type Item = Token;
+impl<'a, S: SourceText<'a>> Iterator for Lexer<'a, S> {
+    type Item = SpannedToken<'a>;
 
-    fn next(&mut self) -> Option<Token> {
-        self.parse_token().ok()
+    fn next(&mut self) -> Option<SpannedToken<'a>> {
+        self.next_token().ok()
     }
 }
\ No newline at end of file
@@ -0,0 +1,196 @@
+//! Runs the compiler binary with the commands that don't need `nasm` or `ld`.
+
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+fn compiler(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_alumina-compiler"))
+        .args(args)
+        .current_dir(env!("CARGO_TARGET_TMPDIR"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Unable to start compiler");
+    child.stdin.take().unwrap().write_all(stdin.as_bytes()).unwrap();
+    child.wait_with_output().expect("Unable to wait for compiler")
+}
+
+fn example(name: &str) -> String {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/programs").join(name).display().to_string()
+}
+
+#[test]
+fn run_interpret_reads_stdin() {
+    let output = compiler(&["run", "--interpret", "-"], "exit 6 * 7");
+    assert_eq!(output.status.code(), Some(42));
+}
+
+#[test]
+fn run_interpret_checks_like_lowering() {
+    let output = compiler(&["run", "--interpret", "-"], "return 3");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("`return` outside of a function"));
+
+    let output = compiler(&["run", "--interpret", "-"], "let a = 1\nif a {\n\tlet a = 2\n}\nexit(a)");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("variable `a` is already declared"));
+}
+
+#[test]
+fn emit_prints_assembly() {
+    let output = compiler(&["emit", "-"], "exit 1");
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("_start:"));
+}
+
+#[test]
+fn emit_tokens_and_ast() {
+    let tokens = compiler(&["emit", "--emit=tokens", "-"], "exit 1");
+    assert!(String::from_utf8_lossy(&tokens.stdout).contains("IntLiteral(1)"));
+
+    let ast = compiler(&["emit", "--emit=ast", "-"], "exit 1");
+    assert!(String::from_utf8_lossy(&ast.stdout).contains("Exit at 1:1"));
+}
+
+#[test]
+fn emit_ast_snapshots() {
+    let ast = compiler(&["emit", "--emit=ast", "-"], "let x = 1 + 2 * 3\nexit(x)");
+    assert_eq!(String::from_utf8_lossy(&ast.stdout), "\
+Let `x` at 1:1
+`-- Add at 1:11
+    |-- Literal 1 at 1:9
+    `-- Mul at 1:15
+        |-- Literal 2 at 1:13
+        `-- Literal 3 at 1:17
+Exit at 2:1
+`-- Ident `x` at 2:6
+");
+
+    let dot = compiler(&["emit", "--emit=ast-dot", "-"], "exit(-1)");
+    assert_eq!(String::from_utf8_lossy(&dot.stdout), "\
+digraph {
+    node [shape=box];
+    n3 [label=\"Block at 0:0\"];
+    n2 [label=\"Exit at 1:1\"];
+    n1 [label=\"Neg at 1:6\"];
+    n0 [label=\"Literal 1 at 1:7\"];
+    n3 -> n2;
+    n2 -> n1;
+    n1 -> n0;
+}
+");
+}
+
+#[test]
+fn emit_ir_snapshot() {
+    let ir = compiler(&["emit", "--emit=ir", "-"], "let x = 1\nif x > 0 {\n\tx = x + 2\n}\nexit(x)");
+    assert_eq!(String::from_utf8_lossy(&ir.stdout), "\
+fn _start() {
+bb0:
+    %0 = 1
+    %1 = gt %0, 0
+    branch %1, bb1, bb2
+bb1:
+    %0 = add %0, 2
+    jump bb2
+bb2:
+    exit %0
+}
+");
+}
+
+#[test]
+fn optimised_ir_snapshot() {
+    let source = "let x = 1\nlet two = 2\nwhile x < 100 {\n\tx = x * two\n}\nexit(x)";
+    let ir = compiler(&["emit", "--emit=ir", "-O1", "-"], source);
+    assert_eq!(String::from_utf8_lossy(&ir.stdout), "\
+fn _start() {
+bb0:
+    jump bb1
+bb1:
+    %5 = phi [bb0: 1], [bb2: %6]
+    %2 = lt %5, 100
+    branch %2, bb2, bb3
+bb2:
+    %6 = mul %5, 2
+    jump bb1
+bb3:
+    exit %5
+}
+");
+
+    let output = compiler(&["run", "--interpret", "-O2", "-"], source);
+    assert_eq!(output.status.code(), Some(128));
+}
+
+#[test]
+fn folding_removes_dead_branches() {
+    let source = "let a = 2 * 3 + 4\nif 1 == 1 {\n\ta = a + 1\n} else {\n\ta = 0\n}\nwhile 0 {\n\ta = 0\n}\nexit(a)";
+    let ir = compiler(&["emit", "--emit=ir", "-"], source);
+    assert_eq!(String::from_utf8_lossy(&ir.stdout), "\
+fn _start() {
+bb0:
+    %0 = 10
+    %0 = add %0, 1
+    exit %0
+}
+");
+}
+
+#[test]
+fn folding_diagnostics() {
+    let output = compiler(&["check", "-"], "let a = 1\nexit(a / (2 - 2))");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("divide by zero"));
+
+    // Only an error in code that can run
+    let output = compiler(&["check", "-"], "if 0 {\n\texit(1 / 0)\n}");
+    assert!(output.status.success());
+
+    // Only a warning in code that might not run, which traps if it does
+    let source = "fn check(x) {\n\tif x {\n\t\texit(1 / 0)\n\t}\n\treturn 0\n}\nexit(check(0) + 3)";
+    let output = compiler(&["check", "-"], source);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("divide by zero if it runs"));
+    let output = compiler(&["run", "--interpret", "-"], source);
+    assert_eq!(output.status.code(), Some(3));
+    let output = compiler(&["run", "--interpret", "-"], &source.replace("check(0)", "check(1)"));
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("attempt to divide by zero"));
+
+    let output = compiler(&["run", "--interpret", "-"], "exit(9223372036854775807 + 1 < 0)");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("wraps around to -9223372036854775808"));
+
+    let output = compiler(&["run", "--interpret", "-"], "exit(-9223372036854775808 < 0)");
+    assert_eq!(output.status.code(), Some(1));
+    let output = compiler(&["check", "-"], "exit 9223372036854775808");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("literal out of range"));
+}
+
+#[test]
+fn check_several_files() {
+    let output = compiler(&["check", &example("arithmetic.alo"), &example("functions.alo")], "");
+    assert!(output.status.success());
+
+    let output = compiler(&["check", &example("arithmetic.alo"), "-"], "exit 1 +");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("<stdin>:1:9"));
+
+    // A later file succeeding doesn't hide an earlier failure
+    let output = compiler(&["check", "-", &example("arithmetic.alo")], "exit 1 +");
+    assert_eq!(output.status.code(), Some(1));
+    let output = compiler(&["emit", "missing.alo", &example("arithmetic.alo")], "");
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn usage_errors() {
+    for args in [&["run", "a.alo", "b.alo"][..], &["-o", "out", "a.alo", "b.alo"], &["--emit=wasm", "a.alo"], &["-O3", "a.alo"], &["check"]] {
+        let output = compiler(args, "");
+        assert_eq!(output.status.code(), Some(2), "{args:?}");
+    }
+}
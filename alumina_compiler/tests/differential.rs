@@ -0,0 +1,181 @@
+//! Runs every example program through both the native code generator and the
+//! interpreter, checking that they agree on the exit code.
+//!
+//! Programs in `tests/programs` also declare their expected exit code on the
+//! first line, as `// exit: <code>`. Native code is built at every optimisation
+//! level, which needs `nasm` and `ld`. Without them the tests fail, unless
+//! `ALUMINA_SKIP_NATIVE=1` is set to only check the interpreter.
+
+extern crate alumina_compiler;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use alumina_compiler::asm;
+use alumina_compiler::folding::Folder;
+use alumina_compiler::generation::Generator;
+use alumina_compiler::interpreter::Interpreter;
+use alumina_compiler::lowering::Lowerer;
+use alumina_compiler::optimisation::{self, OptLevel};
+use alumina_compiler::peephole;
+use alumina_compiler::parser::Parser;
+use alumina_compiler::token::Lexer;
+
+const EXIT_HEADER: &str = "// exit:";
+const SKIP_NATIVE_VAR: &str = "ALUMINA_SKIP_NATIVE";
+const OPT_LEVELS: [(OptLevel, &str); 3] = [(OptLevel::O0, "O0"), (OptLevel::O1, "O1"), (OptLevel::O2, "O2")];
+
+fn programs() -> Vec<PathBuf> {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let mut programs = Vec::new();
+    for dir in [manifest_dir.join("../examples"), manifest_dir.join("tests/programs")] {
+        for entry in fs::read_dir(&dir).expect("Unable to read program directory") {
+            let path = entry.expect("Unable to read directory entry").path();
+            if path.extension().is_some_and(|ext| ext == "alo") {
+                programs.push(path);
+            }
+        }
+    }
+    programs.sort();
+    programs
+}
+
+fn expected_exit_code(source: &str) -> Option<u8> {
+    let header = source.lines().next()?.strip_prefix(EXIT_HEADER)?;
+    Some(header.trim().parse().expect("Exit code header must be a number from 0 to 255"))
+}
+
+fn native_toolchain_available() -> bool {
+    ["nasm", "ld"].iter().all(|tool| Command::new(tool).arg("--version").output().is_ok())
+}
+
+/// Whether to build and run native code, which is only skipped when the run opts out
+fn check_native() -> bool {
+    if env::var_os(SKIP_NATIVE_VAR).is_some_and(|value| value == "1") {
+        eprintln!("{SKIP_NATIVE_VAR} is set, only checking the interpreter");
+        return false;
+    }
+    assert!(
+        native_toolchain_available(),
+        "nasm or ld not found, install them or set {}=1 to only check the interpreter",
+        SKIP_NATIVE_VAR
+    );
+    true
+}
+
+/// Assembles, links and runs the generated code, returning its exit code
+fn run_native(name: &str, code: &str) -> Result<u8, String> {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("differential");
+    fs::create_dir_all(&dir).map_err(|err| err.to_string())?;
+    let asm = dir.join(format!("{name}.asm"));
+    let object = dir.join(format!("{name}.o"));
+    let binary = dir.join(name);
+    fs::write(&asm, code).map_err(|err| err.to_string())?;
+
+    let status = Command::new("nasm").arg("-felf64").arg("-o").arg(&object).arg(&asm)
+        .status().map_err(|err| err.to_string())?;
+    if !status.success() {
+        return Err(format!("nasm failed with {status}"));
+    }
+    let status = Command::new("ld").arg("-o").arg(&binary).arg(&object)
+        .status().map_err(|err| err.to_string())?;
+    if !status.success() {
+        return Err(format!("ld failed with {status}"));
+    }
+
+    let status = Command::new(&binary).status().map_err(|err| err.to_string())?;
+    status.code()
+        .map(|code| code as u8)
+        .ok_or_else(|| format!("terminated by {status}"))
+}
+
+/// Checks a single program, returning a description of any mismatch
+fn check_program(path: &Path, native: bool) -> Result<(), String> {
+    let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+    let source = fs::read_to_string(path).map_err(|err| err.to_string())?;
+    check_source(&name, &source, native)
+}
+
+fn check_source(name: &str, source: &str, native: bool) -> Result<(), String> {
+    let tokens = Lexer::tokenize(source.as_bytes()).map_err(|err| format!("lexer error: {err:?}"))?;
+    let (ast, errors) = Parser::parse(tokens.into_iter());
+    if !errors.is_empty() {
+        return Err(format!("parser errors: {errors:?}"));
+    }
+    // The interpreter runs the tree as parsed, so it also checks the folding
+    let mut folded = ast.clone();
+    Folder::fold(&mut folded).map_err(|err| format!("folding error: {err:?}"))?;
+    let program = Lowerer::lower_program(&folded).map_err(|err| format!("generator error: {err:?}"))?;
+    let interpreted = Interpreter::run(&ast).map_err(|err| format!("interpreter error: {err:?}"))?;
+
+    if let Some(expected) = expected_exit_code(source) {
+        if interpreted != expected {
+            return Err(format!("interpreter exited with {interpreted}, expected {expected}"));
+        }
+    }
+
+    for (level, flag) in OPT_LEVELS {
+        let mut program = program.clone();
+        optimisation::optimise(&mut program, level);
+        let mut lines = Generator::generate_lines(&program);
+        if level != OptLevel::O0 {
+            peephole::optimise(&mut lines);
+        }
+        let code = asm::render(&lines);
+        if native {
+            let compiled = run_native(&format!("{name}-{flag}"), &code)?;
+            if compiled != interpreted {
+                return Err(format!("native code at -{flag} exited with {compiled}, interpreter with {interpreted}"));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn native_and_interpreter_agree() {
+    let native = check_native();
+
+    let programs = programs();
+    assert!(!programs.is_empty(), "No programs found");
+
+    let failures: Vec<String> = programs.iter()
+        .filter_map(|path| check_program(path, native).err().map(|err| format!("{}: {err}", path.display())))
+        .collect();
+
+    assert!(failures.is_empty(), "{} of {} programs failed:\n{}", failures.len(), programs.len(), failures.join("\n"));
+}
+
+/// Builds a balanced expression tree, which needs one register per level to evaluate without spilling
+fn balanced_expr(depth: u32, leaf: &mut i64) -> String {
+    if depth == 0 {
+        *leaf = *leaf % 9 + 1;
+        return leaf.to_string();
+    }
+    let op = if depth.is_multiple_of(2) { "+" } else { "-" };
+    format!("({} {op} {})", balanced_expr(depth - 1, leaf), balanced_expr(depth - 1, leaf))
+}
+
+#[test]
+fn register_pressure() {
+    let native = check_native();
+    let mut leaf = 0;
+    let programs = [
+        // More levels than there are registers, so operands have to be spilled
+        format!("exit({})", balanced_expr(13, &mut leaf)),
+        // Calls count as needing every register, and take the values held in registers with them
+        format!(
+            "fn id(x) {{\n\treturn x\n}}\nlet a = 7\nexit({} / (id(2) + id(a)) - (id(100) - id(3)) * (a + id(id(1) * 2)))",
+            balanced_expr(11, &mut leaf)
+        ),
+    ];
+
+    for (index, source) in programs.iter().enumerate() {
+        if let Err(err) = check_source(&format!("register_pressure{index}"), source, native) {
+            panic!("register_pressure{}: {}", index, err);
+        }
+    }
+}
@@ -0,0 +1,92 @@
+//! Checks that lexing from a stream and from source already in memory give the
+//! same tokens.
+
+extern crate alumina_compiler;
+
+use std::borrow::Cow;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+use alumina_compiler::token::{Lexer, LexerError, LexerOptions, Token};
+
+#[test]
+fn streamed_and_in_memory_agree() {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    for dir in [manifest_dir.join("../examples"), manifest_dir.join("tests/programs")] {
+        for entry in fs::read_dir(&dir).expect("Unable to read program directory") {
+            let path = entry.expect("Unable to read directory entry").path();
+            let source = fs::read_to_string(&path).expect("Unable to read program");
+            let streamed = Lexer::tokenize(source.as_bytes()).expect("Unable to lex program");
+            let in_memory = Lexer::tokenize_str(&source).expect("Unable to lex program");
+            assert_eq!(streamed, in_memory, "{}", path.display());
+        }
+    }
+}
+
+#[test]
+fn byte_order_mark_and_keyword_case() {
+    let source = "\u{FEFF}LET été = 1";
+    let tokens = Lexer::tokenize_str(source).unwrap();
+    assert_eq!(tokens, Lexer::tokenize(source.as_bytes()).unwrap());
+
+    assert_eq!(tokens[0].token, Token::Let);
+    assert_eq!((tokens[0].span.offset, tokens[0].span.column), (3, 1));
+    assert_eq!(tokens[1].token, Token::Ident("été".into()));
+    assert_eq!(tokens[1].span.len, "été".len());
+}
+
+#[test]
+fn identifiers_borrow_from_source_in_memory() {
+    let source = "let name = 1 // note";
+    let options = LexerOptions { trivia: true };
+    let tokens: Vec<_> = Lexer::from_text(source, options).map(|spanned| spanned.token).collect();
+    assert!(matches!(&tokens[1], Token::Ident(Cow::Borrowed(name)) if name.as_ptr() == source[4..].as_ptr()));
+    assert!(matches!(&tokens[4], Token::LineComment(Cow::Borrowed(" note"))));
+
+    let streamed = Lexer::tokenize(source.as_bytes()).unwrap();
+    assert!(matches!(&streamed[1].token, Token::Ident(Cow::Owned(name)) if name == "name"));
+
+    let source = "/* outer /* inner */ */ exit 1";
+    let token = Lexer::from_text(source, options).next().map(|spanned| spanned.token);
+    assert!(matches!(token, Some(Token::BlockComment(Cow::Borrowed(" outer /* inner */ ")))));
+    let streamed = Lexer::with_options(source.as_bytes(), options).next().map(|spanned| spanned.token);
+    assert!(matches!(streamed, Some(Token::BlockComment(Cow::Owned(text))) if text == " outer /* inner */ "));
+}
+
+#[test]
+fn invalid_utf8_is_an_error() {
+    let source = b"let a = 1\nexit(a \xFF+ 1)\n";
+    match Lexer::tokenize(&source[..]) {
+        Err(LexerError::InvalidUtf8(span)) => assert_eq!((span.offset, span.line, span.column), (17, 2, 8)),
+        result => panic!("Expected invalid UTF-8, got {:?}", result)
+    }
+
+    let source = b"/* \xC3 */ exit 1";
+    assert!(matches!(Lexer::tokenize(&source[..]), Err(LexerError::InvalidUtf8(span)) if span.offset == 3));
+}
+
+/// Gives out its text, then fails a single read and reports the end of input after that
+struct FailOnce(&'static [u8], bool);
+impl Read for FailOnce {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.0.is_empty() {
+            let len = self.0.len().min(buf.len());
+            buf[..len].copy_from_slice(&self.0[..len]);
+            self.0 = &self.0[len..];
+            return Ok(len);
+        }
+        match std::mem::replace(&mut self.1, true) {
+            false => Err(io::Error::other("disconnected")),
+            true => Ok(0)
+        }
+    }
+}
+
+#[test]
+fn read_errors_inside_a_token() {
+    match Lexer::tokenize(FailOnce(b"exit abc", false)) {
+        Err(LexerError::IOError(err)) => assert_eq!(err.to_string(), "disconnected"),
+        result => panic!("Expected a read error, got {:?}", result)
+    }
+}
@@ -0,0 +1,43 @@
+//! Checks the tree the parser builds when it recovers from errors.
+
+extern crate alumina_compiler;
+extern crate flat_tree;
+
+use alumina_compiler::parser::{NodeKind, Parser, ParserError, UnaryOp};
+use alumina_compiler::token::Lexer;
+use flat_tree::TreeNode;
+
+#[test]
+fn failed_statements_leave_no_nodes() {
+    let tokens = Lexer::tokenize_str("let a = 1 + (2 * 3 +\nexit(4)").unwrap();
+    let (ast, errors) = Parser::parse(tokens.into_iter());
+    assert_eq!(errors.len(), 1);
+
+    // Only the block, the exit and its literal are left
+    let kinds: Vec<NodeKind> = ast.tree.get(ast.root).pre_order().map(|node| node.kind.clone()).collect();
+    assert_eq!(kinds, [NodeKind::Block, NodeKind::Exit, NodeKind::Literal(4)]);
+    assert_eq!(ast.tree.node_count(), kinds.len());
+}
+
+#[test]
+fn smallest_integer_literal() {
+    // Only negating the magnitude of the smallest integer brings it into range
+    let tokens = Lexer::tokenize_str("exit -9223372036854775808").unwrap();
+    let (ast, errors) = Parser::parse(tokens.into_iter());
+    assert!(errors.is_empty());
+    let kinds: Vec<NodeKind> = ast.tree.get(ast.root).pre_order().map(|node| node.kind.clone()).collect();
+    assert_eq!(kinds, [NodeKind::Block, NodeKind::Exit, NodeKind::Literal(i64::MIN)]);
+
+    // Only the innermost negation is part of the literal
+    let tokens = Lexer::tokenize_str("exit --9223372036854775808").unwrap();
+    let (ast, errors) = Parser::parse(tokens.into_iter());
+    assert!(errors.is_empty());
+    let kinds: Vec<NodeKind> = ast.tree.get(ast.root).pre_order().map(|node| node.kind.clone()).collect();
+    assert_eq!(kinds[2..], [NodeKind::Unary(UnaryOp::Neg), NodeKind::Literal(i64::MIN)]);
+
+    for source in ["exit 9223372036854775808", "exit -(9223372036854775808)", "exit 1 - 9223372036854775808"] {
+        let tokens = Lexer::tokenize_str(source).unwrap();
+        let (_, errors) = Parser::parse(tokens.into_iter());
+        assert!(matches!(errors[..], [ParserError::LiteralOutOfRange(_)]), "{}", source);
+    }
+}
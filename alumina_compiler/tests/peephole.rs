@@ -0,0 +1,140 @@
+//! Runs the peephole optimiser over hand written assembly and the generator's output.
+
+extern crate alumina_compiler;
+
+use alumina_compiler::asm::{Line, Operand};
+use alumina_compiler::generation::Generator;
+use alumina_compiler::lowering::Lowerer;
+use alumina_compiler::parser::Parser;
+use alumina_compiler::peephole;
+use alumina_compiler::token::Lexer;
+
+fn reg(name: &'static str) -> Operand {
+    Operand::Register(name)
+}
+
+fn instr(mnemonic: &'static str, operands: &[Operand]) -> Line {
+    Line::Instr(mnemonic, operands.to_vec())
+}
+
+fn label(name: &str) -> Line {
+    Line::Label(name.to_string())
+}
+
+fn optimised(mut lines: Vec<Line>) -> Vec<Line> {
+    peephole::optimise(&mut lines);
+    lines
+}
+
+#[test]
+fn copies_straight_back() {
+    let slot = Operand::Memory("rbp", -8);
+    let lines = vec![
+        instr("mov", &[reg("rbx"), reg("rbx")]),
+        instr("mov", &[reg("r13"), reg("rax")]),
+        instr("mov", &[reg("rax"), reg("r13")]),
+        instr("mov", &[slot.clone(), reg("rax")]),
+        instr("mov", &[reg("rax"), slot.clone()]),
+        instr("mov", &[reg("rax"), reg("rbx")]),
+        instr("mov", &[reg("rcx"), reg("rax")]),
+        instr("mov", &[reg("r12"), reg("rax")]),
+        instr("pop", &[reg("r12")]),
+    ];
+    assert_eq!(optimised(lines), vec![
+        instr("mov", &[reg("r13"), reg("rax")]),
+        instr("mov", &[slot, reg("rax")]),
+        instr("mov", &[reg("rax"), reg("rbx")]),
+        instr("mov", &[reg("rcx"), reg("rax")]),
+        instr("pop", &[reg("r12")]),
+    ]);
+}
+
+#[test]
+fn jumps_and_labels() {
+    let lines = vec![
+        Line::Directive(String::from("global _start")),
+        Line::Directive(String::from("section .text")),
+        label("_start"),
+        label(".text"),
+        label(".bb0"),
+        instr("cmp", &[reg("rbx"), Operand::Immediate(0)]),
+        instr("je", &[Operand::Label(String::from(".bb2"))]),
+        instr("jmp", &[Operand::Label(String::from(".bb1"))]),
+        label(".bb1"),
+        label(".bb2"),
+        instr("ret", &[]),
+        label("fn_unused"),
+        instr("ret", &[]),
+    ];
+    assert_eq!(optimised(lines), vec![
+        Line::Directive(String::from("global _start")),
+        Line::Directive(String::from("section .text")),
+        label("_start"),
+        instr("cmp", &[reg("rbx"), Operand::Immediate(0)]),
+        instr("ret", &[]),
+        label("fn_unused"),
+        instr("ret", &[]),
+    ]);
+}
+
+#[test]
+fn jump_threading() {
+    let jump = |mnemonic, target: &str| instr(mnemonic, &[Operand::Label(target.to_string())]);
+    let lines = vec![
+        label("_start"),
+        jump("je", ".bb1"),
+        instr("ret", &[]),
+        label(".bb1"),
+        jump("jmp", ".bb2"),
+        label(".loop"),
+        jump("jmp", ".loop"),
+        label(".bb2"),
+        jump("jne", ".loop"),
+        instr("ret", &[]),
+    ];
+    // The jump that was jumped to is left behind, unreachable, as its label is no longer needed
+    assert_eq!(optimised(lines), vec![
+        label("_start"),
+        jump("je", ".bb2"),
+        instr("ret", &[]),
+        jump("jmp", ".bb2"),
+        label(".loop"),
+        jump("jmp", ".loop"),
+        label(".bb2"),
+        jump("jne", ".loop"),
+        instr("ret", &[]),
+    ]);
+}
+
+fn contains_run(lines: &[Line], run: &[Line]) -> bool {
+    lines.windows(run.len()).any(|window| window == run)
+}
+
+fn generated(source: &str) -> Vec<Line> {
+    let tokens = Lexer::tokenize_str(source).expect("Unable to lex program");
+    let (ast, errors) = Parser::parse(tokens.into_iter());
+    assert!(errors.is_empty(), "{:?}", errors);
+    Generator::generate_lines(&Lowerer::lower_program(&ast).expect("Unable to lower program"))
+}
+
+#[test]
+fn generated_code() {
+    // The result of the recursive call is stored and then copied straight back to be returned
+    let mut lines = generated("fn gcd(a, b) {\n\tif b == 0 { return a }\n\treturn gcd(b, a - a / b * b)\n}\nexit(gcd(12, 18))");
+    let stored = [instr("mov", &[reg("r13"), reg("rax")]), instr("mov", &[reg("rax"), reg("r13")])];
+    assert!(contains_run(&lines, &stored));
+    peephole::optimise(&mut lines);
+    assert!(!contains_run(&lines, &stored));
+    // Nor is the result kept, as the register is restored on the way out
+    let call = instr("call", &[Operand::Label(String::from("fn_gcd"))]);
+    assert!(contains_run(&lines, &[call, instr("pop", &[reg("r13")])]));
+    assert!(!lines.contains(&label(".bb0")));
+
+    // Leaving the loop jumps to the block that returns, through one that only jumps there
+    let source = "fn scaled(c, a, b) {\n\tlet total = 40\n\tif c {\n\t\tlet i = 0\n\t\twhile i < 10 {\n\t\t\ttotal = total + a * b\n\t\t\ti = i + 1\n\t\t}\n\t}\n\treturn total\n}\nexit(scaled(1, 2, 2))";
+    let mut lines = generated(source);
+    assert!(lines.ends_with(&[label(".bb6"), instr("jmp", &[Operand::Label(String::from(".bb3"))])]));
+    peephole::optimise(&mut lines);
+    assert!(lines.contains(&instr("je", &[Operand::Label(String::from(".bb3"))])));
+    assert!(!lines.contains(&label(".bb6")));
+}
@@ -1,8 +1,19 @@
+use std::borrow::Cow;
+use std::fmt;
 use std::io;
 use std::str;
+
+mod str_reader;
+
+pub use str_reader::StrReader;
+
 #[derive(Debug)]
 pub enum CharReaderError {
     IOError(std::io::Error),
+    /// Byte offset of a sequence that isn't valid UTF-8, which is skipped
+    InvalidUtf8(usize),
+    /// Reset without a mark, or after reading more than the buffer holds past it
+    NoMark,
     ReachedEOF,
 }
 impl From<std::io::Error> for CharReaderError {
@@ -10,23 +21,84 @@ impl From<std::io::Error> for CharReaderError {
         CharReaderError::IOError(err)
     }
 }
-impl From<str::Utf8Error> for CharReaderError {
-    fn from(_: str::Utf8Error) -> Self {
-        CharReaderError::IOError(std::io::Error::new(
-            std::io::ErrorKind::InvalidData,
-            "Invalid UTF8",
-        ))
+
+/// Location of a character within the input
+///
+/// Lines and columns are 1-indexed, the offset is in bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Position {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+impl Default for Position {
+    fn default() -> Self {
+        Position { offset: 0, line: 1, column: 1 }
+    }
+}
+impl Position {
+    /// Moves past a character that took up `len` bytes of input
+    fn advance(&mut self, char: char, len: usize, tab_width: usize) {
+        self.offset += len;
+        match char {
+            '\n' => {
+                self.line += 1;
+                self.column = 1;
+            }
+            '\t' => self.column = (self.column - 1) / tab_width * tab_width + tab_width + 1,
+            _ => self.column += 1,
+        }
+    }
+}
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
     }
 }
 
+/// Source of characters for a lexer, either streamed by a [`CharReader`] or already in memory
+/// in a [`StrReader`]
+///
+/// Text borrowed from the source lives for `'a`
+pub trait SourceText<'a> {
+    fn next_char(&mut self) -> Result<char, CharReaderError>;
+    /// Looks at the character `n` places ahead without consuming anything
+    fn peek_nth(&mut self, n: usize) -> Result<char, CharReaderError>;
+    /// Consumes characters for as long as `predicate` holds, borrowing them from the source
+    /// when it is in memory
+    ///
+    /// The end of input ends the run, any other error is returned
+    fn consume_while(&mut self, predicate: impl FnMut(char) -> bool) -> Result<Cow<'a, str>, CharReaderError>;
+    /// Position of the next character to be read
+    fn position(&self) -> Position;
+    fn mark(&mut self);
+    /// Text read since the mark, or `None` when the source is streamed and can't lend it out
+    fn slice_from_mark(&self) -> Option<&'a str>;
+    fn reset_to_mark(&mut self) -> Result<(), CharReaderError>;
+    fn clear_mark(&mut self);
+}
+
+/// Streams characters from a reader through a fixed size buffer
 pub struct CharReader<R: io::Read> {
     inner: R,
     buf: Box<[u8]>,
     pos: usize,    // Left side
     filled: usize, // Right side
+    /// Position of the next character
+    position: Position,
+    tab_width: usize,
+    /// Replace invalid UTF-8 instead of failing
+    lossy: bool,
+    /// Buffer index and position to return to, bytes after it are kept in the buffer
+    mark: Option<(usize, Position)>,
 }
 
 const DEFAULT_BUF_SIZE: usize = 5_000;
+/// Tabs count as a single column, like any other character
+const DEFAULT_TAB_WIDTH: usize = 1;
+/// Longest UTF-8 sequence, so the buffer always holds a whole character
+const MAX_CHAR_LEN: usize = 4;
+const BYTE_ORDER_MARK: &[u8] = b"\xEF\xBB\xBF";
 
 impl<R: io::Read> CharReader<R> {
     pub fn new(inner: R) -> Self {
@@ -34,36 +106,239 @@ impl<R: io::Read> CharReader<R> {
     }
 
     pub fn with_capacity(capacity: usize, inner: R) -> Self {
+        assert!(capacity >= MAX_CHAR_LEN, "Buffer must fit a whole character");
         let buf = vec![0; capacity].into_boxed_slice();
         Self {
             inner,
             buf,
             pos: 0,
             filled: 0,
+            position: Position::default(),
+            tab_width: DEFAULT_TAB_WIDTH,
+            lossy: false,
+            mark: None,
         }
     }
 
-    pub fn next_char(&mut self) -> Result<char, CharReaderError> {
-        // Enough bytes in the current buffer
-        if self.pos + 4 >= self.filled {
-            // Shift buffer to the start
-            self.buf.copy_within(self.pos..self.filled, 0);
-            self.filled -= self.pos;
-
-            self.filled += self.inner.read(&mut self.buf)?;
-            if self.filled == 0 {
+    /// Replaces each invalid UTF-8 sequence with U+FFFD instead of returning
+    /// [`CharReaderError::InvalidUtf8`]
+    pub fn lossy(mut self) -> Self {
+        self.lossy = true;
+        self
+    }
+
+    /// Makes tabs advance the column to the next multiple of `tab_width`, plus one
+    pub fn with_tab_width(mut self, tab_width: usize) -> Self {
+        assert!(tab_width > 0, "Tab width must be at least 1");
+        self.tab_width = tab_width;
+        self
+    }
+
+    /// Position of the next character to be read
+    ///
+    /// A byte order mark is only skipped once the first character has been read or peeked
+    pub fn position(&self) -> Position {
+        self.position
+    }
+
+    /// Looks at the character `n` places ahead without consuming anything, so `peek_nth(0)` is
+    /// the next character
+    ///
+    /// Panics if `n + 1` characters might not fit in the buffer
+    pub fn peek_nth(&mut self, n: usize) -> Result<char, CharReaderError> {
+        let needed = (n + 1) * MAX_CHAR_LEN;
+        assert!(needed <= self.buf.len(), "Cannot peek further ahead than the buffer holds");
+        self.fill(needed)?;
+
+        let mut at = self.pos;
+        let mut skipped = 0;
+        loop {
+            if at == self.filled {
                 return Err(CharReaderError::ReachedEOF);
             }
-            self.pos = 0;
+            let char = match self.decode(at) {
+                Ok((char, len)) => {
+                    at += len;
+                    char
+                }
+                Err(len) if self.lossy => {
+                    at += len;
+                    char::REPLACEMENT_CHARACTER
+                }
+                Err(_) => return Err(CharReaderError::InvalidUtf8(self.position.offset + at - self.pos)),
+            };
+            if skipped == n {
+                return Ok(char);
+            }
+            skipped += 1;
+        }
+    }
+
+    /// Consumes characters for as long as `predicate` holds, returning them
+    ///
+    /// The end of input ends the run, any other error is returned without consuming the bytes
+    /// that caused it
+    pub fn consume_while(&mut self, mut predicate: impl FnMut(char) -> bool) -> Result<String, CharReaderError> {
+        let mut consumed = String::new();
+        loop {
+            match self.peek_nth(0) {
+                Ok(char) if predicate(char) => {
+                    consumed.push(char);
+                    self.next_char()?;
+                }
+                Ok(_) | Err(CharReaderError::ReachedEOF) => return Ok(consumed),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Remembers the current position so the reader can go back to it with
+    /// [`CharReader::reset_to_mark`], replacing any earlier mark
+    ///
+    /// The mark is dropped once more than the buffer's capacity has been read past it
+    pub fn mark(&mut self) {
+        self.mark = Some((self.pos, self.position));
+    }
+
+    /// Goes back to the mark, using it up
+    pub fn reset_to_mark(&mut self) -> Result<(), CharReaderError> {
+        let (pos, position) = self.mark.take().ok_or(CharReaderError::NoMark)?;
+        self.pos = pos;
+        self.position = position;
+        Ok(())
+    }
+
+    /// Drops the mark, letting the buffer reuse the bytes after it
+    pub fn clear_mark(&mut self) {
+        self.mark = None;
+    }
+
+    /// Iterates over the remaining characters along with the position of each
+    pub fn char_positions(self) -> CharPositions<R> {
+        CharPositions { reader: self }
+    }
+
+    /// Reads the next character, skipping a byte order mark at the start of the input
+    pub fn next_char(&mut self) -> Result<char, CharReaderError> {
+        self.next_positioned().map(|(_, char)| char)
+    }
+
+    fn next_positioned(&mut self) -> Result<(Position, char), CharReaderError> {
+        self.fill(MAX_CHAR_LEN)?;
+        if self.pos == self.filled {
+            return Err(CharReaderError::ReachedEOF);
+        }
+
+        let (char, len) = match self.decode(self.pos) {
+            Ok(decoded) => decoded,
+            Err(len) if self.lossy => (char::REPLACEMENT_CHARACTER, len),
+            Err(len) => {
+                let offset = self.position.offset;
+                self.pos += len;
+                self.position.offset += len;
+                return Err(CharReaderError::InvalidUtf8(offset));
+            }
+        };
+        self.pos += len;
+
+        let position = self.position;
+        self.position.advance(char, len, self.tab_width);
+        Ok((position, char))
+    }
+
+    /// Buffers at least `needed` bytes past the current one, unless the input ends first, and
+    /// skips a byte order mark at the start of the input
+    fn fill(&mut self, needed: usize) -> io::Result<()> {
+        if self.filled - self.pos < needed {
+            self.read_more(needed)?;
         }
 
-        let char = str::from_utf8(&self.buf[self.pos..self.filled])?
-            .chars()
-            .next()
-            .expect("&str must be at least length 1");
+        if self.position.offset == 0 && self.buf[self.pos..self.filled].starts_with(BYTE_ORDER_MARK) {
+            self.pos += BYTE_ORDER_MARK.len();
+            self.position.offset += BYTE_ORDER_MARK.len();
+            return self.fill(needed);
+        }
+        Ok(())
+    }
 
-        self.pos += char.len_utf8();
-        Ok(char)
+    fn read_more(&mut self, needed: usize) -> io::Result<()> {
+
+        // Keep the bytes after the mark, unless that leaves no room
+        if let Some((mark, _)) = self.mark {
+            if self.pos - mark + needed > self.buf.len() {
+                self.mark = None;
+            }
+        }
+        let keep = self.mark.map_or(self.pos, |(mark, _)| mark);
+
+        // Shift buffer to the start
+        self.buf.copy_within(keep..self.filled, 0);
+        self.filled -= keep;
+        self.pos -= keep;
+        if let Some((ref mut mark, _)) = self.mark {
+            *mark = 0;
+        }
+
+        while self.filled - self.pos < needed && self.filled < self.buf.len() {
+            match self.inner.read(&mut self.buf[self.filled..]) {
+                Ok(0) => break,
+                Ok(read) => self.filled += read,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    /// Decodes the character starting at buffer index `at`, or gives the length of the invalid
+    /// sequence there
+    fn decode(&self, at: usize) -> Result<(char, usize), usize> {
+        let bytes = &self.buf[at..self.filled.min(at + MAX_CHAR_LEN)];
+        let valid = match str::from_utf8(bytes) {
+            Ok(text) => text,
+            // Only the first character has to be valid
+            Err(err) if err.valid_up_to() > 0 => str::from_utf8(&bytes[..err.valid_up_to()]).unwrap(),
+            // A sequence cut short by the end of input is invalid as a whole
+            Err(err) => return Err(err.error_len().unwrap_or(bytes.len())),
+        };
+        let char = valid.chars().next().expect("&str must be at least length 1");
+        Ok((char, char.len_utf8()))
+    }
+
+}
+
+impl<'a, R: io::Read> SourceText<'a> for CharReader<R> {
+    fn next_char(&mut self) -> Result<char, CharReaderError> {
+        CharReader::next_char(self)
+    }
+
+    fn peek_nth(&mut self, n: usize) -> Result<char, CharReaderError> {
+        CharReader::peek_nth(self, n)
+    }
+
+    fn consume_while(&mut self, predicate: impl FnMut(char) -> bool) -> Result<Cow<'a, str>, CharReaderError> {
+        CharReader::consume_while(self, predicate).map(Cow::Owned)
+    }
+
+    fn position(&self) -> Position {
+        self.position
+    }
+
+    fn mark(&mut self) {
+        CharReader::mark(self)
+    }
+
+    /// The buffer is reused, so nothing can be borrowed from it for `'a`
+    fn slice_from_mark(&self) -> Option<&'a str> {
+        None
+    }
+
+    fn reset_to_mark(&mut self) -> Result<(), CharReaderError> {
+        CharReader::reset_to_mark(self)
+    }
+
+    fn clear_mark(&mut self) {
+        CharReader::clear_mark(self)
     }
 }
 
@@ -75,6 +350,24 @@ impl<R: io::Read> Iterator for CharReader<R> {
     }
 }
 
+/// Characters paired with their positions, see [`CharReader::char_positions`]
+pub struct CharPositions<R: io::Read> {
+    reader: CharReader<R>,
+}
+impl<R: io::Read> CharPositions<R> {
+    /// Position of the next character to be read
+    pub fn position(&self) -> Position {
+        self.reader.position
+    }
+}
+impl<R: io::Read> Iterator for CharPositions<R> {
+    type Item = (Position, char);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.reader.next_positioned().ok()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::fs::File;
@@ -91,7 +384,7 @@ mod tests {
 
     #[test]
     fn file_read() {
-        let file = File::open("../test.alo").expect("Unable to open file");
+        let file = File::open("../examples/test.alo").expect("Unable to open file");
 
         let mut reader = CharReader::new(file);
         let mut string = String::new();
@@ -99,11 +392,251 @@ mod tests {
         loop {
             match reader.next_char() {
                 Ok(ch) => string.push(ch),
-                Err(err) if matches!(err, CharReaderError::ReachedEOF) => break,
-                Err(err) => assert!(false, "Reader failed unexpectedly: {:?}", err),
+                Err(CharReaderError::ReachedEOF) => break,
+                Err(err) => panic!("Reader failed unexpectedly: {:?}", err),
             }
         }
 
         println!("Output:\n{}", string)
     }
+
+    #[test]
+    fn read_past_end() {
+        let bytes: &[u8] = b"a";
+        let mut reader = CharReader::new(bytes);
+        assert_eq!(reader.next_char().unwrap(), 'a');
+        for _ in 0..3 {
+            assert!(matches!(reader.next_char(), Err(CharReaderError::ReachedEOF)));
+        }
+    }
+
+    #[test]
+    fn refills_keep_unread_bytes() {
+        let text = "let x = 10\nexit(x + 20)\n";
+        let mut reader = CharReader::with_capacity(8, text.as_bytes());
+        let mut string = String::new();
+        while let Ok(ch) = reader.next_char() {
+            string.push(ch);
+        }
+        assert_eq!(string, text);
+        assert_eq!(reader.position().offset, text.len());
+    }
+
+    /// Gives out a single byte per read, so every character straddles two reads
+    struct Trickle<'a>(&'a [u8]);
+    impl<'a> io::Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.split_first() {
+                Some((&byte, rest)) if !buf.is_empty() => {
+                    buf[0] = byte;
+                    self.0 = rest;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
+        }
+    }
+
+    fn read_all<R: io::Read>(mut reader: CharReader<R>) -> (String, Vec<usize>) {
+        let mut string = String::new();
+        let mut invalid = Vec::new();
+        loop {
+            match reader.next_char() {
+                Ok(ch) => string.push(ch),
+                Err(CharReaderError::InvalidUtf8(offset)) => invalid.push(offset),
+                Err(CharReaderError::ReachedEOF) => return (string, invalid),
+                Err(err) => panic!("Reader failed unexpectedly: {:?}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn multibyte_across_reads() {
+        let text = "aé€😀b\n€€€€€";
+        assert_eq!(read_all(CharReader::new(Trickle(text.as_bytes()))).0, text);
+        assert_eq!(read_all(CharReader::with_capacity(5, text.as_bytes())).0, text);
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        // A stray continuation byte, a truncated sequence and a surrogate
+        let bytes: &[u8] = b"a\x80b\xE2\x82c\xED\xA0\x80d";
+        let (string, invalid) = read_all(CharReader::new(bytes));
+        assert_eq!(string, "abcd");
+        assert_eq!(invalid, [1, 3, 6, 7, 8]);
+
+        let (string, invalid) = read_all(CharReader::new(bytes).lossy());
+        assert_eq!(string, "a\u{FFFD}b\u{FFFD}c\u{FFFD}\u{FFFD}\u{FFFD}d");
+        assert!(invalid.is_empty());
+
+        // Cut short by the end of input
+        let bytes: &[u8] = b"ab\xF0\x9F\x98";
+        assert_eq!(read_all(CharReader::new(Trickle(bytes))), (String::from("ab"), vec![2]));
+    }
+
+    #[test]
+    fn lossy_positions() {
+        let bytes: &[u8] = b"\xFF\xFFa";
+        let positions: Vec<_> = CharReader::new(bytes).lossy().char_positions().collect();
+        assert_eq!(positions, [(at(0, 1, 1), '\u{FFFD}'), (at(1, 1, 2), '\u{FFFD}'), (at(2, 1, 3), 'a')]);
+    }
+
+    #[test]
+    fn byte_order_mark() {
+        let bytes: &[u8] = "\u{FEFF}a\u{FEFF}".as_bytes();
+        let positions: Vec<_> = CharReader::new(bytes).char_positions().collect();
+        assert_eq!(positions, [(at(3, 1, 1), 'a'), (at(4, 1, 2), '\u{FEFF}')]);
+    }
+
+    #[test]
+    fn peek_nth() {
+        let bytes: &[u8] = "\u{FEFF}a€\nb".as_bytes();
+        let mut reader = CharReader::with_capacity(20, Trickle(bytes));
+        assert_eq!(reader.peek_nth(2).unwrap(), '\n');
+        assert_eq!(reader.peek_nth(0).unwrap(), 'a');
+        assert!(matches!(reader.peek_nth(4), Err(CharReaderError::ReachedEOF)));
+        assert_eq!(reader.position(), at(3, 1, 1));
+
+        assert_eq!(reader.next_char().unwrap(), 'a');
+        assert_eq!(reader.peek_nth(1).unwrap(), '\n');
+        assert_eq!(reader.position(), at(4, 1, 2));
+
+        let bytes: &[u8] = b"a\xFFb";
+        assert!(matches!(CharReader::new(bytes).peek_nth(1), Err(CharReaderError::InvalidUtf8(1))));
+        assert_eq!(CharReader::new(bytes).lossy().peek_nth(2).unwrap(), 'b');
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot peek further ahead than the buffer holds")]
+    fn peek_past_buffer() {
+        let bytes: &[u8] = b"abcdef";
+        CharReader::with_capacity(8, bytes).peek_nth(2).ok();
+    }
+
+    #[test]
+    fn consume_while() {
+        let bytes: &[u8] = b"123abc 4";
+        let mut reader = CharReader::with_capacity(4, bytes);
+        assert_eq!(reader.consume_while(|ch| ch.is_numeric()).unwrap(), "123");
+        assert_eq!(reader.consume_while(|ch| ch.is_numeric()).unwrap(), "");
+        assert_eq!(reader.consume_while(|ch| ch != ' ').unwrap(), "abc");
+        assert_eq!(reader.position(), at(6, 1, 7));
+        assert_eq!(reader.consume_while(|_| true).unwrap(), " 4");
+
+        // Invalid UTF-8 is reported rather than ending the run, and isn't consumed
+        let bytes: &[u8] = b"ab\xFFc";
+        let mut reader = CharReader::new(bytes);
+        assert!(matches!(reader.consume_while(char::is_alphabetic), Err(CharReaderError::InvalidUtf8(2))));
+        assert_eq!(reader.position(), at(2, 1, 3));
+    }
+
+    #[test]
+    fn mark_and_reset() {
+        let bytes: &[u8] = b"let x\n= 10";
+        let mut reader = CharReader::with_capacity(8, Trickle(bytes));
+        reader.consume_while(|ch| ch != ' ').unwrap();
+        reader.mark();
+        assert_eq!(reader.consume_while(|ch| ch != '=').unwrap(), " x\n");
+        reader.reset_to_mark().unwrap();
+        assert_eq!(reader.position(), at(3, 1, 4));
+        assert_eq!(reader.next_char().unwrap(), ' ');
+
+        // The mark is used up by resetting
+        assert!(matches!(reader.reset_to_mark(), Err(CharReaderError::NoMark)));
+
+        // Reading more than the buffer past the mark drops it
+        reader.mark();
+        assert_eq!(reader.consume_while(|_| true).unwrap(), "x\n= 10");
+        assert!(matches!(reader.reset_to_mark(), Err(CharReaderError::NoMark)));
+    }
+
+    fn drain<'a, S: SourceText<'a>>(mut source: S) -> Vec<(Position, char)> {
+        let mut chars = Vec::new();
+        loop {
+            // Peek first so a byte order mark is skipped before asking for the position
+            let _ = source.peek_nth(0);
+            let position = source.position();
+            match source.next_char() {
+                Ok(ch) => chars.push((position, ch)),
+                Err(CharReaderError::ReachedEOF) => return chars,
+                Err(err) => panic!("Reader failed unexpectedly: {:?}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn str_reader_matches_char_reader() {
+        let text = "\u{FEFF}let x = 1\n\té€ // 😀\n";
+        let streamed = drain(CharReader::with_capacity(4, text.as_bytes()).with_tab_width(4));
+        assert_eq!(drain(StrReader::new(text).with_tab_width(4)), streamed);
+        assert_eq!(streamed[0], (at(3, 1, 1), 'l'));
+    }
+
+    #[test]
+    fn str_reader_borrows() {
+        let mut reader = StrReader::new("abc12 é");
+        assert!(matches!(reader.consume_while(char::is_alphabetic), Ok(Cow::Borrowed("abc"))));
+        assert_eq!(reader.peek_nth(2).unwrap(), ' ');
+        reader.mark();
+        assert_eq!(reader.consume_while(|ch| ch != 'é').unwrap(), "12 ");
+        assert_eq!(reader.position(), at(6, 1, 7));
+        reader.reset_to_mark().unwrap();
+        assert_eq!(reader.remaining(), "12 é");
+        assert_eq!(reader.position(), at(3, 1, 4));
+
+        reader.mark();
+        reader.consume_while(char::is_numeric).unwrap();
+        assert_eq!(reader.slice_from_mark(), Some("12"));
+        assert_eq!(CharReader::new(&b"12"[..]).slice_from_mark(), None);
+
+        assert!(matches!(StrReader::from_bytes(b"ab\xFF"), Err(CharReaderError::InvalidUtf8(2))));
+    }
+
+    fn at(offset: usize, line: usize, column: usize) -> Position {
+        Position { offset, line, column }
+    }
+
+    #[test]
+    fn positions() {
+        let bytes: &[u8] = "ab\né\nc".as_bytes();
+        let positions: Vec<_> = CharReader::new(bytes).char_positions().collect();
+        assert_eq!(
+            positions,
+            [
+                (at(0, 1, 1), 'a'),
+                (at(1, 1, 2), 'b'),
+                (at(2, 1, 3), '\n'),
+                (at(3, 2, 1), 'é'),
+                (at(5, 2, 2), '\n'),
+                (at(6, 3, 1), 'c'),
+            ]
+        );
+    }
+
+    #[test]
+    fn position_at_end() {
+        let bytes: &[u8] = "a\n€".as_bytes();
+        let mut reader = CharReader::new(bytes);
+        while reader.next_char().is_ok() {}
+        assert_eq!(reader.position(), at(5, 2, 2));
+    }
+
+    #[test]
+    fn tab_stops() {
+        let bytes: &[u8] = b"\ta\tbc\t\td";
+        let columns: Vec<_> = CharReader::new(bytes)
+            .with_tab_width(4)
+            .char_positions()
+            .map(|(position, char)| (char, position.column))
+            .collect();
+        assert_eq!(
+            columns,
+            [('\t', 1), ('a', 5), ('\t', 6), ('b', 9), ('c', 10), ('\t', 11), ('\t', 13), ('d', 17)]
+        );
+
+        let bytes: &[u8] = b"\ta";
+        let mut reader = CharReader::new(bytes);
+        reader.next_char().unwrap();
+        assert_eq!(reader.position().column, 2);
+    }
 }
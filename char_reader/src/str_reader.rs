@@ -0,0 +1,108 @@
+use std::borrow::Cow;
+use std::str;
+
+use super::{CharReaderError, Position, SourceText, BYTE_ORDER_MARK, DEFAULT_TAB_WIDTH};
+
+/// Reads characters from source text that is already in memory, without copying it
+pub struct StrReader<'a> {
+    source: &'a str,
+    /// Byte index of the next character
+    pos: usize,
+    position: Position,
+    tab_width: usize,
+    mark: Option<(usize, Position)>,
+}
+
+impl<'a> StrReader<'a> {
+    /// Skips a byte order mark at the start of `source`
+    pub fn new(source: &'a str) -> Self {
+        let mut reader = StrReader {
+            source,
+            pos: 0,
+            position: Position::default(),
+            tab_width: DEFAULT_TAB_WIDTH,
+            mark: None,
+        };
+        if source.as_bytes().starts_with(BYTE_ORDER_MARK) {
+            reader.pos = BYTE_ORDER_MARK.len();
+            reader.position.offset = BYTE_ORDER_MARK.len();
+        }
+        reader
+    }
+
+    /// Fails with the offset of the first invalid sequence if `bytes` isn't UTF-8
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Self, CharReaderError> {
+        str::from_utf8(bytes)
+            .map(StrReader::new)
+            .map_err(|err| CharReaderError::InvalidUtf8(err.valid_up_to()))
+    }
+
+    /// Makes tabs advance the column to the next multiple of `tab_width`, plus one
+    pub fn with_tab_width(mut self, tab_width: usize) -> Self {
+        assert!(tab_width > 0, "Tab width must be at least 1");
+        self.tab_width = tab_width;
+        self
+    }
+
+    /// Text that hasn't been read yet
+    pub fn remaining(&self) -> &'a str {
+        &self.source[self.pos..]
+    }
+}
+
+impl<'a> SourceText<'a> for StrReader<'a> {
+    fn next_char(&mut self) -> Result<char, CharReaderError> {
+        let char = self.remaining().chars().next().ok_or(CharReaderError::ReachedEOF)?;
+        self.pos += char.len_utf8();
+        self.position.advance(char, char.len_utf8(), self.tab_width);
+        Ok(char)
+    }
+
+    fn peek_nth(&mut self, n: usize) -> Result<char, CharReaderError> {
+        self.remaining().chars().nth(n).ok_or(CharReaderError::ReachedEOF)
+    }
+
+    fn consume_while(&mut self, mut predicate: impl FnMut(char) -> bool) -> Result<Cow<'a, str>, CharReaderError> {
+        let remaining = self.remaining();
+        let len = remaining
+            .find(|char| !predicate(char))
+            .unwrap_or(remaining.len());
+        for char in remaining[..len].chars() {
+            self.position.advance(char, char.len_utf8(), self.tab_width);
+        }
+        self.pos += len;
+        Ok(Cow::Borrowed(&remaining[..len]))
+    }
+
+    fn position(&self) -> Position {
+        self.position
+    }
+
+    /// Marks are never dropped, as the whole source is in memory
+    fn mark(&mut self) {
+        self.mark = Some((self.pos, self.position));
+    }
+
+    fn slice_from_mark(&self) -> Option<&'a str> {
+        self.mark.map(|(pos, _)| &self.source[pos..self.pos])
+    }
+
+    fn reset_to_mark(&mut self) -> Result<(), CharReaderError> {
+        let (pos, position) = self.mark.take().ok_or(CharReaderError::NoMark)?;
+        self.pos = pos;
+        self.position = position;
+        Ok(())
+    }
+
+    fn clear_mark(&mut self) {
+        self.mark = None;
+    }
+}
+
+impl<'a> Iterator for StrReader<'a> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_char().ok()
+    }
+}
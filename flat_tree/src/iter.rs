@@ -0,0 +1,139 @@
+use std::collections::VecDeque;
+
+use crate::{FlatTree, Node, TreeNode};
+
+/// Direct children of a node, see [`TreeNode::children`]
+pub struct Children<'a, T> {
+    tree: &'a FlatTree<T>,
+    next: Option<usize>,
+}
+impl<'a, T> Children<'a, T> {
+    pub(crate) fn new(tree: &'a FlatTree<T>, index: usize) -> Children<'a, T> {
+        Children { tree, next: tree.links[index].first_child }
+    }
+}
+impl<'a, T> Iterator for Children<'a, T> {
+    type Item = Node<'a, T>;
+
+    fn next(&mut self) -> Option<Node<'a, T>> {
+        let index = self.next?;
+        self.next = self.tree.links[index].next_sibling;
+        Some(self.tree.node(index))
+    }
+}
+
+/// Ancestors of a node, nearest first, see [`TreeNode::ancestors`]
+pub struct Ancestors<'a, T> {
+    tree: &'a FlatTree<T>,
+    next: Option<usize>,
+}
+impl<'a, T> Ancestors<'a, T> {
+    pub(crate) fn new(tree: &'a FlatTree<T>, index: usize) -> Ancestors<'a, T> {
+        Ancestors { tree, next: tree.links[index].parent }
+    }
+}
+impl<'a, T> Iterator for Ancestors<'a, T> {
+    type Item = Node<'a, T>;
+
+    fn next(&mut self) -> Option<Node<'a, T>> {
+        let index = self.next?;
+        self.next = self.tree.links[index].parent;
+        Some(self.tree.node(index))
+    }
+}
+
+/// Depth first traversal visiting parents before children, see [`TreeNode::pre_order`]
+///
+/// Follows the sibling links, so needs no extra memory
+pub struct PreOrder<'a, T> {
+    tree: &'a FlatTree<T>,
+    root: usize,
+    next: Option<usize>,
+}
+impl<'a, T> PreOrder<'a, T> {
+    pub(crate) fn new(tree: &'a FlatTree<T>, root: usize) -> PreOrder<'a, T> {
+        PreOrder { tree, root, next: Some(root) }
+    }
+}
+impl<'a, T> Iterator for PreOrder<'a, T> {
+    type Item = Node<'a, T>;
+
+    fn next(&mut self) -> Option<Node<'a, T>> {
+        let index = self.next?;
+        let links = &self.tree.links;
+
+        self.next = links[index].first_child.or_else(|| {
+            // Climb until a node with a later sibling, without leaving the subtree
+            let mut current = index;
+            loop {
+                if current == self.root {
+                    return None;
+                }
+                if let Some(next_sibling) = links[current].next_sibling {
+                    return Some(next_sibling);
+                }
+                current = links[current].parent?;
+            }
+        });
+
+        Some(self.tree.node(index))
+    }
+}
+
+/// Depth first traversal visiting children before parents, see [`TreeNode::post_order`]
+///
+/// Follows the sibling links, so needs no extra memory
+pub struct PostOrder<'a, T> {
+    tree: &'a FlatTree<T>,
+    root: usize,
+    next: Option<usize>,
+}
+impl<'a, T> PostOrder<'a, T> {
+    pub(crate) fn new(tree: &'a FlatTree<T>, root: usize) -> PostOrder<'a, T> {
+        PostOrder { tree, root, next: Some(PostOrder::first_leaf(tree, root)) }
+    }
+
+    fn first_leaf(tree: &FlatTree<T>, mut index: usize) -> usize {
+        while let Some(first_child) = tree.links[index].first_child {
+            index = first_child;
+        }
+        index
+    }
+}
+impl<'a, T> Iterator for PostOrder<'a, T> {
+    type Item = Node<'a, T>;
+
+    fn next(&mut self) -> Option<Node<'a, T>> {
+        let index = self.next?;
+        let links = &self.tree.links[index];
+
+        self.next = match (index == self.root, links.next_sibling) {
+            (true, _) => None,
+            (false, Some(next_sibling)) => Some(PostOrder::first_leaf(self.tree, next_sibling)),
+            (false, None) => links.parent,
+        };
+
+        Some(self.tree.node(index))
+    }
+}
+
+/// Traversal visiting nodes level by level, see [`TreeNode::breadth_first`]
+pub struct BreadthFirst<'a, T> {
+    tree: &'a FlatTree<T>,
+    queue: VecDeque<usize>,
+}
+impl<'a, T> BreadthFirst<'a, T> {
+    pub(crate) fn new(tree: &'a FlatTree<T>, root: usize) -> BreadthFirst<'a, T> {
+        BreadthFirst { tree, queue: VecDeque::from([root]) }
+    }
+}
+impl<'a, T> Iterator for BreadthFirst<'a, T> {
+    type Item = Node<'a, T>;
+
+    fn next(&mut self) -> Option<Node<'a, T>> {
+        let index = self.queue.pop_front()?;
+        let node = self.tree.node(index);
+        self.queue.extend(node.children().map(|child| child.get_index()));
+        Some(node)
+    }
+}
@@ -1,4 +1,10 @@
-use std::ops::Deref;
+use std::fmt;
+use std::ops::{Deref, DerefMut, Index, IndexMut};
+
+mod iter;
+mod render;
+
+pub use iter::{Ancestors, BreadthFirst, Children, PostOrder, PreOrder};
 
 pub trait TreeNode {
     type Item;
@@ -6,11 +12,60 @@ pub trait TreeNode {
     fn get_tree(&self) -> &FlatTree<Self::Item>;
     fn get_index(&self) -> usize;
 
-    fn get_parent(&self) -> Option<Node<Self::Item>> {
+    fn get_parent(&self) -> Option<Node<'_, Self::Item>> {
         self.get_tree().parent_of(self.get_index())
     }
     fn get_item(&self) -> &Self::Item {
-        self.get_tree().buf.get(self.get_index()).unwrap()
+        self.get_tree().buf.get(self.get_index()).unwrap().as_ref().unwrap()
+    }
+    /// Generational id of the node, which stops resolving once the node is removed
+    fn get_id(&self) -> NodeId {
+        let index = self.get_index();
+        NodeId { index, generation: self.get_tree().generations[index] }
+    }
+
+    fn first_child(&self) -> Option<Node<'_, Self::Item>> {
+        self.get_tree().link(self.get_index(), |links| links.first_child)
+    }
+    fn last_child(&self) -> Option<Node<'_, Self::Item>> {
+        self.get_tree().link(self.get_index(), |links| links.last_child)
+    }
+    fn next_sibling(&self) -> Option<Node<'_, Self::Item>> {
+        self.get_tree().link(self.get_index(), |links| links.next_sibling)
+    }
+    fn prev_sibling(&self) -> Option<Node<'_, Self::Item>> {
+        self.get_tree().link(self.get_index(), |links| links.prev_sibling)
+    }
+
+    /// Direct children, in the order they were attached
+    fn children(&self) -> Children<'_, Self::Item> {
+        Children::new(self.get_tree(), self.get_index())
+    }
+    /// Every node below this one, in pre-order
+    fn descendants(&self) -> PreOrder<'_, Self::Item> {
+        let mut iter = self.pre_order();
+        iter.next();
+        iter
+    }
+    /// This node and its descendants, each node before its children
+    fn pre_order(&self) -> PreOrder<'_, Self::Item> {
+        PreOrder::new(self.get_tree(), self.get_index())
+    }
+    /// This node and its descendants, each node after its children
+    fn post_order(&self) -> PostOrder<'_, Self::Item> {
+        PostOrder::new(self.get_tree(), self.get_index())
+    }
+    /// This node and its descendants, level by level
+    fn breadth_first(&self) -> BreadthFirst<'_, Self::Item> {
+        BreadthFirst::new(self.get_tree(), self.get_index())
+    }
+    /// Parent, grandparent and so on up to the root
+    fn ancestors(&self) -> Ancestors<'_, Self::Item> {
+        Ancestors::new(self.get_tree(), self.get_index())
+    }
+    /// Number of ancestors, so 0 for a root
+    fn depth(&self) -> usize {
+        self.ancestors().count()
     }
 }
 
@@ -62,20 +117,159 @@ impl<T> TreeNode for NodeMut<'_, T> {
     }
 }
 impl<T> NodeMut<'_, T> {
-    fn set_parent(self, parent: usize) -> Self {
-        self.tree.set_parent(self.id, parent);
+    pub fn set_parent(self, parent: NodeId) -> Self {
+        let id = self.get_id();
+        self.tree.set_parent(id, parent);
         self
     }
 }
+impl<T> Deref for NodeMut<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.get_item()
+    }
+}
+impl<T> DerefMut for NodeMut<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.tree.buf[self.id].as_mut().unwrap()
+    }
+}
 
 
 const SPARE_CAPACITY: usize = 10;
+
+/// A node's index along with the generation of its slot
+///
+/// Removing a node or compacting the tree moves slots to a new generation, so
+/// [`FlatTree::try_get`] can tell a stale id apart from one for a live node
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NodeId {
+    index: usize,
+    generation: u32,
+}
+impl NodeId {
+    pub fn index(self) -> usize {
+        self.index
+    }
+}
+
+/// New ids of moved nodes, from [`FlatTree::compact`] and [`FlatTree::graft`]
 #[derive(Debug, PartialEq, Clone)]
+pub struct Remap {
+    indices: Vec<Option<usize>>,
+    /// Generation of each old slot, which an id needs to be remapped
+    old_generations: Vec<u32>,
+    /// Generation of every moved node
+    generation: u32,
+}
+impl Remap {
+    /// New id of a node, or `None` if it was removed, including before the nodes were moved
+    pub fn get(&self, id: NodeId) -> Option<NodeId> {
+        if self.old_generations.get(id.index) != Some(&id.generation) {
+            return None;
+        }
+        self.index(id.index).map(|index| NodeId { index, generation: self.generation })
+    }
+
+    fn index(&self, index: usize) -> Option<usize> {
+        self.indices.get(index).copied().flatten()
+    }
+
+    /// Links only ever point at live nodes, so they always have a new index
+    fn links(&self, links: Links) -> Links {
+        let remap = |index: Option<usize>| index.and_then(|index| self.index(index));
+        Links {
+            parent: remap(links.parent),
+            first_child: remap(links.first_child),
+            last_child: remap(links.last_child),
+            prev_sibling: remap(links.prev_sibling),
+            next_sibling: remap(links.next_sibling),
+        }
+    }
+}
+
+/// A broken invariant found by [`FlatTree::validate`]
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum InvariantError {
+    /// The per slot storage disagrees on the number of slots
+    LengthMismatch,
+    /// A link points past the end of the tree or at a removed node
+    DanglingLink { index: usize, target: usize },
+    /// A node's links disagree with those of its parent or siblings
+    InconsistentLinks(usize),
+    /// Following parents from a node leads back to it
+    Cycle(usize),
+}
+impl fmt::Display for InvariantError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvariantError::LengthMismatch => write!(f, "storage lengths disagree"),
+            InvariantError::DanglingLink { index, target } => write!(f, "node {index} links to missing node {target}"),
+            InvariantError::InconsistentLinks(index) => write!(f, "links of node {index} are inconsistent"),
+            InvariantError::Cycle(index) => write!(f, "node {index} is its own ancestor"),
+        }
+    }
+}
+impl std::error::Error for InvariantError {}
+
+/// Links from a node to its neighbours, kept up to date as parents are set
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Links {
+    parent: Option<usize>,
+    first_child: Option<usize>,
+    last_child: Option<usize>,
+    prev_sibling: Option<usize>,
+    next_sibling: Option<usize>,
+}
+
+/// A tree stored in flat buffers, with each node linked to its parent, children and siblings
+///
+/// With the `serde` feature it serializes as its slots and their links, and is validated when
+/// deserialized
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "RawTree<T>"))]
 pub struct FlatTree<T> {
-    buf: Vec<T>,
-    parents: Vec<Option<usize>>,
+    /// Values of each slot, `None` once removed
+    buf: Vec<Option<T>>,
+    links: Vec<Links>,
+    generations: Vec<u32>,
+    /// Generation of newly added slots, raised by compaction so ids of the removed nodes that
+    /// used to sit past the new end don't match the nodes added there later
+    generation: u32,
+    #[cfg_attr(feature = "serde", serde(skip_serializing))]
     len: usize
 }
+
+/// Serialized form of a [`FlatTree`], checked by [`FlatTree::validate`] before use
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct RawTree<T> {
+    buf: Vec<Option<T>>,
+    links: Vec<Links>,
+    generations: Vec<u32>,
+    generation: u32,
+}
+#[cfg(feature = "serde")]
+impl<T> TryFrom<RawTree<T>> for FlatTree<T> {
+    type Error = InvariantError;
+
+    fn try_from(raw: RawTree<T>) -> Result<FlatTree<T>, InvariantError> {
+        let RawTree { buf, links, generations, generation } = raw;
+        let len = buf.len();
+        let tree = FlatTree { buf, links, generations, generation, len };
+        tree.validate()?;
+        Ok(tree)
+    }
+}
+impl<T> Default for FlatTree<T> {
+    fn default() -> FlatTree<T> {
+        FlatTree::new()
+    }
+}
 impl<T> FlatTree<T> {
     pub fn new() -> FlatTree<T> {
         FlatTree::with_capacity(SPARE_CAPACITY * 2)
@@ -83,40 +277,448 @@ impl<T> FlatTree<T> {
 
     pub fn with_capacity(capacity: usize) -> FlatTree<T> {
         let buf = Vec::with_capacity(capacity);
-        let parents = Vec::with_capacity(capacity);
-        FlatTree { buf, parents, len: capacity }
+        let links = Vec::with_capacity(capacity);
+        let generations = Vec::with_capacity(capacity);
+        FlatTree { buf, links, generations, generation: 0, len: 0 }
     }
 
     pub fn add(&mut self, value: T) -> NodeMut<'_, T> {
-        self.buf.push(value);
-        self.parents.push(None);
+        self.buf.push(Some(value));
+        self.links.push(Links::default());
+        self.generations.push(self.generation);
         self.len += 1;
+        self.check_invariants([self.len - 1]);
         let id = self.len - 1;
         NodeMut { tree: self, id }
     }
 
-    pub fn set_parent(&mut self, index: usize, parent: usize) {
+    /// Makes `id` the last child of `parent`, moving it from any previous parent
+    ///
+    /// Panics if `parent` is `id` itself or one of its descendants
+    pub fn set_parent(&mut self, id: NodeId, parent: NodeId) {
+        let index = self.index_of(id);
+        let parent = self.index_of(parent);
         assert!(
-            self.len >= index,
-            "Cannot access values that haven't been set"
+            parent != index && !self.node(parent).ancestors().any(|node| node.id == index),
+            "Cannot make a node its own ancestor"
         );
-        unsafe { *self.parents.get_unchecked_mut(index) = Some(parent) }
+        let Links { parent: old_parent, prev_sibling, next_sibling, .. } = self.links[index];
+        self.attach(index, parent);
+        self.check_invariants([Some(index), old_parent, prev_sibling, next_sibling].into_iter().flatten());
+    }
+
+    /// Makes `index` the last child of `parent`, moving it from any previous parent
+    fn attach(&mut self, index: usize, parent: usize) {
+        self.unlink(index);
+
+        let last_child = self.links[parent].last_child;
+        match last_child {
+            Some(last_child) => self.links[last_child].next_sibling = Some(index),
+            None => self.links[parent].first_child = Some(index),
+        }
+        self.links[parent].last_child = Some(index);
+
+        let links = &mut self.links[index];
+        links.parent = Some(parent);
+        links.prev_sibling = last_child;
+    }
+
+    /// Panics if the node was never added or has been removed, see [`FlatTree::try_get`]
+    pub fn get(&self, id: NodeId) -> Node<'_, T> {
+        Node { tree: self, id: self.index_of(id) }
+    }
+
+    /// Looks up a node by id, or `None` if it has since been removed or moved
+    pub fn try_get(&self, id: NodeId) -> Option<Node<'_, T>> {
+        match self.generations.get(id.index) {
+            Some(&generation) if generation == id.generation && self.buf[id.index].is_some() => {
+                Some(Node { tree: self, id: id.index })
+            },
+            _ => None
+        }
+    }
+
+    /// Panics if the node was never added or has been removed
+    pub fn get_mut(&mut self, id: NodeId) -> NodeMut<'_, T> {
+        let index = self.index_of(id);
+        NodeMut { tree: self, id: index }
+    }
+
+    /// Whether an id still refers to a live node
+    pub fn contains(&self, id: NodeId) -> bool {
+        self.try_get(id).is_some()
+    }
+
+    /// Number of live nodes, not counting the slots of removed ones
+    pub fn node_count(&self) -> usize {
+        self.buf.iter().filter(|value| value.is_some()).count()
     }
 
-    pub fn get(&self, index: usize) -> Node<T> {
+    /// Removes a node from its parent, leaving it as the root of its own subtree
+    pub fn detach(&mut self, id: NodeId) {
+        let index = self.index_of(id);
+        let Links { parent, prev_sibling, next_sibling, .. } = self.links[index];
+        self.unlink(index);
+        self.check_invariants([Some(index), parent, prev_sibling, next_sibling].into_iter().flatten());
+    }
+
+    /// Removes a node and all of its descendants, returning their values in pre-order
+    ///
+    /// The slots are left empty until the tree is compacted
+    pub fn remove_subtree(&mut self, id: NodeId) -> Vec<T> {
+        self.detach(id);
+
+        let indices: Vec<usize> = self.get(id).pre_order().map(|node| node.get_index()).collect();
+        let removed = indices.iter()
+            .map(|&index| {
+                self.links[index] = Links::default();
+                self.generations[index] = self.generations[index].wrapping_add(1);
+                self.buf[index].take().unwrap()
+            })
+            .collect();
+        self.check_invariants(indices);
+        removed
+    }
+
+    /// Puts the subtree at `replacement` where the subtree at `index` is, then removes the latter
+    ///
+    /// Returns the values of the removed subtree in pre-order
+    pub fn replace_with(&mut self, id: NodeId, replacement_id: NodeId) -> Vec<T> {
+        let index = self.index_of(id);
+        let replacement = self.index_of(replacement_id);
+        assert!(
+            !self.node(replacement).pre_order().chain(self.node(replacement).ancestors()).any(|node| node.get_index() == index),
+            "Cannot replace a node with one of its own relatives"
+        );
+        let old_links = self.links[replacement];
+        self.unlink(replacement);
+
+        let Links { parent, prev_sibling, next_sibling, .. } = self.links[index];
+        if let Some(parent) = parent {
+            match prev_sibling {
+                Some(prev_sibling) => self.links[prev_sibling].next_sibling = Some(replacement),
+                None => self.links[parent].first_child = Some(replacement),
+            }
+            match next_sibling {
+                Some(next_sibling) => self.links[next_sibling].prev_sibling = Some(replacement),
+                None => self.links[parent].last_child = Some(replacement),
+            }
+            let links = &mut self.links[replacement];
+            links.parent = Some(parent);
+            links.prev_sibling = prev_sibling;
+            links.next_sibling = next_sibling;
+
+            self.links[index].parent = None;
+            self.links[index].prev_sibling = None;
+            self.links[index].next_sibling = None;
+        }
+
+        let removed = self.remove_subtree(id);
+        let old_neighbours = [old_links.parent, old_links.prev_sibling, old_links.next_sibling];
+        self.check_invariants([Some(replacement)].into_iter().chain(old_neighbours).flatten());
+        removed
+    }
+
+    /// Moves every node of `other` into this tree, attaching its roots as the last children of `under`
+    ///
+    /// Returns where each node of `other` ended up
+    pub fn graft(&mut self, other: FlatTree<T>, under: NodeId) -> Remap {
+        let under = self.index_of(under);
+        let start = self.len;
+
+        let mut next = self.buf.len();
+        let remap = Remap {
+            indices: other.buf.iter()
+                .map(|value| value.as_ref().map(|_| {
+                    next += 1;
+                    next - 1
+                }))
+                .collect(),
+            old_generations: other.generations.clone(),
+            generation: self.generation,
+        };
+
+        let mut roots = Vec::new();
+        for (value, links) in other.buf.into_iter().zip(other.links) {
+            let Some(value) = value else {
+                continue;
+            };
+            if links.parent.is_none() {
+                roots.push(self.buf.len());
+            }
+            self.buf.push(Some(value));
+            self.links.push(remap.links(links));
+            self.generations.push(remap.generation);
+            self.len += 1;
+        }
+
+        for root in roots {
+            self.attach(root, under);
+        }
+        self.check_invariants([under].into_iter().chain(start..self.len));
+
+        remap
+    }
+
+    /// Reclaims the slots of removed nodes, moving the remaining nodes to close the gaps
+    ///
+    /// Every existing [`NodeId`] stops resolving, the returned [`Remap`] gives each node's new index
+    pub fn compact(&mut self) -> Remap {
+        let generation = self.generations.iter().fold(self.generation, |max, &generation| max.max(generation)).wrapping_add(1);
+        let mut next = 0;
+        let remap = Remap {
+            indices: self.buf.iter()
+                .map(|value| value.as_ref().map(|_| {
+                    next += 1;
+                    next - 1
+                }))
+                .collect(),
+            old_generations: self.generations.clone(),
+            generation,
+        };
+
+        let buf = std::mem::take(&mut self.buf);
+        let links = std::mem::take(&mut self.links);
+        for (value, links) in buf.into_iter().zip(links) {
+            if value.is_some() {
+                self.buf.push(value);
+                self.links.push(remap.links(links));
+            }
+        }
+        self.generations = vec![generation; self.buf.len()];
+        self.generation = generation;
+        self.len = self.buf.len();
+        // Every node moved, and checking them all costs about as much as moving them
+        if cfg!(debug_assertions) {
+            assert_valid(self.validate());
+        }
+
+        remap
+    }
+
+    /// Checks that every link points at a live node, that parent, child and sibling links agree
+    /// and that no node is its own ancestor
+    pub fn validate(&self) -> Result<(), InvariantError> {
+        if self.buf.len() != self.len || self.links.len() != self.len || self.generations.len() != self.len {
+            return Err(InvariantError::LengthMismatch);
+        }
+
+        // Check every link first so the structural checks below can follow them
+        (0..self.len).try_for_each(|index| self.validate_links(index))?;
+        (0..self.len).try_for_each(|index| self.validate_structure(index))
+    }
+
+    /// Checks that a node's links point at live nodes, and that an empty slot has no links
+    fn validate_links(&self, index: usize) -> Result<(), InvariantError> {
+        let links = &self.links[index];
+        if self.buf[index].is_none() {
+            return match *links == Links::default() {
+                true => Ok(()),
+                false => Err(InvariantError::InconsistentLinks(index)),
+            };
+        }
+
+        let targets = [links.parent, links.first_child, links.last_child, links.prev_sibling, links.next_sibling];
+        for target in targets.into_iter().flatten() {
+            if target >= self.len || self.buf[target].is_none() {
+                return Err(InvariantError::DanglingLink { index, target });
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that a node agrees with its parent, siblings and children, and isn't its own ancestor
+    ///
+    /// The node's own links must already be valid, the links of nodes it reaches are checked
+    /// before they are followed
+    fn validate_structure(&self, index: usize) -> Result<(), InvariantError> {
+        let links = &self.links[index];
+        if self.buf[index].is_none() {
+            return Ok(());
+        }
+
+        // The node must be reachable from its parent's child list, among siblings with the same parent
+        match (links.parent, links.prev_sibling) {
+            (None, None) if links.next_sibling.is_none() => (),
+            (None, _) => return Err(InvariantError::InconsistentLinks(index)),
+            (Some(parent), None) if self.links[parent].first_child != Some(index) => {
+                return Err(InvariantError::InconsistentLinks(index));
+            },
+            (Some(parent), Some(prev_sibling)) => {
+                let prev_links = &self.links[prev_sibling];
+                if prev_links.next_sibling != Some(index) || prev_links.parent != Some(parent) {
+                    return Err(InvariantError::InconsistentLinks(index));
+                }
+            },
+            _ => (),
+        }
+        match (links.parent, links.next_sibling) {
+            (Some(parent), None) if self.links[parent].last_child != Some(index) => {
+                return Err(InvariantError::InconsistentLinks(index));
+            },
+            (_, Some(next_sibling)) if self.links[next_sibling].prev_sibling != Some(index) => {
+                return Err(InvariantError::InconsistentLinks(index));
+            },
+            _ => (),
+        }
+
+        // Every child must point back, ending at the last child without looping
+        let mut prev_sibling = None;
+        let mut child = links.first_child;
+        let mut count = 0;
+        while let Some(current) = child {
+            self.validate_links(current)?;
+            let child_links = &self.links[current];
+            if child_links.parent != Some(index) || child_links.prev_sibling != prev_sibling || count == self.len {
+                return Err(InvariantError::InconsistentLinks(index));
+            }
+            prev_sibling = child;
+            child = child_links.next_sibling;
+            count += 1;
+        }
+        if prev_sibling != links.last_child {
+            return Err(InvariantError::InconsistentLinks(index));
+        }
+
+        let mut ancestor = links.parent;
+        let mut depth = 0;
+        while let Some(current) = ancestor {
+            if current == index || depth == self.len {
+                return Err(InvariantError::Cycle(index));
+            }
+            self.validate_links(current)?;
+            ancestor = self.links[current].parent;
+            depth += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Checks the links of the nodes a mutation changed in debug builds
+    ///
+    /// Each touched node is only checked against the nodes it links to directly, so this costs about
+    /// as much as the mutation itself. [`FlatTree::validate`] checks the whole tree
+    fn check_invariants(&self, touched: impl IntoIterator<Item = usize>) {
+        if cfg!(debug_assertions) {
+            assert_valid(touched.into_iter().try_for_each(|index| self.validate_local(index)));
+        }
+    }
+
+    /// Checks that a node's links point at live nodes, and that its parent, first and last child and
+    /// siblings link back to it
+    fn validate_local(&self, index: usize) -> Result<(), InvariantError> {
+        self.validate_links(index)?;
+        let links = &self.links[index];
+        let linked = [links.parent, links.first_child, links.last_child, links.prev_sibling, links.next_sibling];
+        for target in linked.into_iter().flatten() {
+            self.validate_links(target)?;
+        }
+
+        let consistent = match links.parent {
+            None => links.prev_sibling.is_none() && links.next_sibling.is_none(),
+            Some(parent) => {
+                let parent_links = &self.links[parent];
+                let prev = match links.prev_sibling {
+                    None => parent_links.first_child == Some(index),
+                    Some(prev_sibling) => {
+                        let prev_links = &self.links[prev_sibling];
+                        prev_links.next_sibling == Some(index) && prev_links.parent == Some(parent)
+                    },
+                };
+                let next = match links.next_sibling {
+                    None => parent_links.last_child == Some(index),
+                    Some(next_sibling) => {
+                        let next_links = &self.links[next_sibling];
+                        next_links.prev_sibling == Some(index) && next_links.parent == Some(parent)
+                    },
+                };
+                prev && next
+            },
+        };
+        let first_child = links.first_child.map_or(links.last_child.is_none(), |first_child| {
+            let child_links = &self.links[first_child];
+            child_links.parent == Some(index) && child_links.prev_sibling.is_none()
+        });
+        let last_child = links.last_child.map_or(links.first_child.is_none(), |last_child| {
+            let child_links = &self.links[last_child];
+            child_links.parent == Some(index) && child_links.next_sibling.is_none()
+        });
+
+        match consistent && first_child && last_child {
+            true => Ok(()),
+            false => Err(InvariantError::InconsistentLinks(index)),
+        }
+    }
+
+    /// Index of a live node, panicking if it was never added or has been removed
+    fn index_of(&self, id: NodeId) -> usize {
         assert!(
-            self.len >= index,
+            id.index < self.len,
             "Cannot access values that haven't been set"
         );
+        assert!(
+            self.contains(id),
+            "Cannot access a node that has been removed"
+        );
+        id.index
+    }
+
+    pub(crate) fn node(&self, index: usize) -> Node<'_, T> {
         Node { tree: self, id: index }
     }
 
-    fn parent_of(&self, index: usize) -> Option<Node<T>> {
+    fn parent_of(&self, index: usize) -> Option<Node<'_, T>> {
+        self.link(index, |links| links.parent)
+    }
+
+    fn link(&self, index: usize, link: impl Fn(&Links) -> Option<usize>) -> Option<Node<'_, T>> {
         assert!(
-            self.len >= index,
+            index < self.len,
             "Cannot access values that haven't been set"
         );
-        unsafe { self.parents.get_unchecked(index).map(|index| Node { tree: self, id: index }) }
+        link(&self.links[index]).map(|index| Node { tree: self, id: index })
+    }
+
+    /// Removes a node from its parent's children, leaving it as a root
+    fn unlink(&mut self, index: usize) {
+        let Links { parent, prev_sibling, next_sibling, .. } = self.links[index];
+        let Some(parent) = parent else {
+            return;
+        };
+
+        match prev_sibling {
+            Some(prev_sibling) => self.links[prev_sibling].next_sibling = next_sibling,
+            None => self.links[parent].first_child = next_sibling,
+        }
+        match next_sibling {
+            Some(next_sibling) => self.links[next_sibling].prev_sibling = prev_sibling,
+            None => self.links[parent].last_child = prev_sibling,
+        }
+
+        let links = &mut self.links[index];
+        links.parent = None;
+        links.prev_sibling = None;
+        links.next_sibling = None;
+    }
+}
+
+fn assert_valid(result: Result<(), InvariantError>) {
+    if let Err(err) = result {
+        panic!("FlatTree invariant broken: {err}");
+    }
+}
+
+impl<T> Index<NodeId> for FlatTree<T> {
+    type Output = T;
+
+    fn index(&self, id: NodeId) -> &T {
+        self.buf[self.index_of(id)].as_ref().unwrap()
+    }
+}
+impl<T> IndexMut<NodeId> for FlatTree<T> {
+    fn index_mut(&mut self, id: NodeId) -> &mut T {
+        let index = self.index_of(id);
+        self.buf[index].as_mut().unwrap()
     }
 }
 
@@ -124,12 +726,17 @@ impl<T> FlatTree<T> {
 mod tests {
     use super::*;
 
+    /// Id of a node that hasn't been removed or moved
+    fn id(index: usize) -> NodeId {
+        NodeId { index, generation: 0 }
+    }
+
     #[test]
     fn it_works() {
         let mut tree = FlatTree::new();
         tree.add(2);
 
-        let node = tree.get(0);
+        let node = tree.get(id(0));
         assert_eq!(2, *node);
 
         assert_eq!(None, node.get_parent())
@@ -147,19 +754,510 @@ mod tests {
     #[should_panic(expected = "Cannot access values that haven't been set")]
     fn stop_illegal_access() {
         let tree: FlatTree<i16> = FlatTree::new();
-        tree.get(10);
+        tree.get(id(10));
     }
 
     #[test]
     fn parent() {
         let mut tree = FlatTree::new();
         tree.add("Mother");
-        let child = {
-            let child = tree.add("Child");
-            child.set_parent(0);
-            child.into()
-        }
+        let child = tree.add("Child").set_parent(id(0)).get_id();
+        let child = tree.get(child);
         let parent = child.get_parent().unwrap();
-        assert_eq!(parent, tree.get(0))
+        assert_eq!(parent, tree.get(id(0)))
+    }
+
+    /// Root with children a and b, where a has children c and d and b has child e
+    fn sample() -> FlatTree<&'static str> {
+        let mut tree = FlatTree::new();
+        for value in ["root", "a", "b", "c", "d", "e"] {
+            tree.add(value);
+        }
+        for (index, parent) in [(1, 0), (2, 0), (3, 1), (4, 1), (5, 2)] {
+            tree.set_parent(id(index), id(parent));
+        }
+        tree
+    }
+
+    fn values<'a>(nodes: impl Iterator<Item = Node<'a, &'static str>>) -> Vec<&'static str> {
+        nodes.map(|node| *node.get_item()).collect()
+    }
+
+    #[test]
+    fn children() {
+        let tree = sample();
+        assert_eq!(values(tree.get(id(0)).children()), ["a", "b"]);
+        assert_eq!(values(tree.get(id(1)).children()), ["c", "d"]);
+        assert_eq!(values(tree.get(id(3)).children()), Vec::<&str>::new());
+
+        assert_eq!(tree.get(id(0)).first_child().unwrap().get_index(), 1);
+        assert_eq!(tree.get(id(0)).last_child().unwrap().get_index(), 2);
+        assert_eq!(tree.get(id(3)).next_sibling().unwrap().get_index(), 4);
+        assert_eq!(tree.get(id(4)).prev_sibling().unwrap().get_index(), 3);
+        assert!(tree.get(id(2)).next_sibling().is_none());
+    }
+
+    #[test]
+    fn traversals() {
+        let tree = sample();
+        let root = tree.get(id(0));
+        assert_eq!(values(root.pre_order()), ["root", "a", "c", "d", "b", "e"]);
+        assert_eq!(values(root.post_order()), ["c", "d", "a", "e", "b", "root"]);
+        assert_eq!(values(root.breadth_first()), ["root", "a", "b", "c", "d", "e"]);
+        assert_eq!(values(root.descendants()), ["a", "c", "d", "b", "e"]);
+    }
+
+    #[test]
+    fn traversals_stay_in_subtree() {
+        let tree = sample();
+        let a = tree.get(id(1));
+        assert_eq!(values(a.pre_order()), ["a", "c", "d"]);
+        assert_eq!(values(a.post_order()), ["c", "d", "a"]);
+        assert_eq!(values(a.breadth_first()), ["a", "c", "d"]);
+        assert_eq!(values(tree.get(id(5)).post_order()), ["e"]);
+    }
+
+    #[test]
+    fn ancestors_and_depth() {
+        let tree = sample();
+        assert_eq!(values(tree.get(id(4)).ancestors()), ["a", "root"]);
+        assert_eq!(tree.get(id(4)).depth(), 2);
+        assert_eq!(tree.get(id(0)).depth(), 0);
+    }
+
+    #[test]
+    fn reparenting_moves_children() {
+        let mut tree = sample();
+        tree.set_parent(id(3), id(2));
+        assert_eq!(values(tree.get(id(1)).children()), ["d"]);
+        assert_eq!(values(tree.get(id(2)).children()), ["e", "c"]);
+        assert!(tree.get(id(4)).prev_sibling().is_none());
+        assert_eq!(tree.get(id(3)).prev_sibling().unwrap().get_index(), 5);
+        assert_eq!(values(tree.get(id(0)).pre_order()), ["root", "a", "d", "b", "e", "c"]);
+        assert_eq!(tree.validate(), Ok(()));
+    }
+
+    #[test]
+    fn remove_subtree() {
+        let mut tree = sample();
+        let c = tree.get(id(3)).get_id();
+        assert_eq!(tree.remove_subtree(id(1)), ["a", "c", "d"]);
+        assert_eq!(values(tree.get(id(0)).pre_order()), ["root", "b", "e"]);
+        assert!(!tree.contains(c));
+        assert!(tree.contains(tree.get(id(5)).get_id()));
+        assert_eq!(tree.validate(), Ok(()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot access a node that has been removed")]
+    fn stop_removed_access() {
+        let mut tree = sample();
+        tree.remove_subtree(id(2));
+        tree.get(id(5));
+    }
+
+    #[test]
+    fn detach() {
+        let mut tree = sample();
+        tree.detach(id(1));
+        assert_eq!(values(tree.get(id(0)).children()), ["b"]);
+        assert!(tree.get(id(1)).get_parent().is_none());
+        assert_eq!(values(tree.get(id(1)).pre_order()), ["a", "c", "d"]);
+        assert_eq!(tree.validate(), Ok(()));
+    }
+
+    #[test]
+    fn replace_with() {
+        let mut tree = sample();
+        let replacement = tree.add("f").get_id();
+        tree.add("g").set_parent(replacement);
+
+        assert_eq!(tree.replace_with(id(1), replacement), ["a", "c", "d"]);
+        assert_eq!(values(tree.get(id(0)).pre_order()), ["root", "f", "g", "b", "e"]);
+        assert_eq!(tree.get(id(2)).prev_sibling().unwrap().get_id(), replacement);
+        assert_eq!(tree.validate(), Ok(()));
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot replace a node with one of its own relatives")]
+    fn replace_with_descendant() {
+        let mut tree = sample();
+        tree.replace_with(id(1), id(3));
+    }
+
+    #[test]
+    fn graft() {
+        let mut tree = sample();
+        let mut other = sample();
+        other.remove_subtree(id(2));
+
+        let remap = tree.graft(other, id(5));
+        assert_eq!(remap.get(id(0)), Some(id(6)));
+        assert_eq!(remap.get(id(2)), None);
+        assert_eq!(remap.get(id(4)), Some(id(9)));
+        assert_eq!(values(tree.get(id(2)).pre_order()), ["b", "e", "root", "a", "c", "d"]);
+        assert_eq!(tree.validate(), Ok(()));
+    }
+
+    #[test]
+    fn compact() {
+        let mut tree = sample();
+        tree.remove_subtree(id(1));
+
+        let remap = tree.compact();
+        assert_eq!(remap.get(id(1)), None);
+        let (root, e) = (remap.get(id(0)).unwrap(), remap.get(id(5)).unwrap());
+        assert_eq!(e.index(), 2);
+        assert_eq!(values(tree.get(root).pre_order()), ["root", "b", "e"]);
+        assert_eq!(values(tree.get(e).ancestors()), ["b", "root"]);
+
+        // Compacting invalidates every id, including ones whose index didn't change
+        assert!(tree.try_get(id(0)).is_none());
+        assert_eq!(*tree.try_get(root).unwrap(), "root");
+    }
+
+    #[test]
+    fn edit_in_place() {
+        let mut tree = sample();
+        *tree.get_mut(id(1)) = "z";
+        tree[id(2)] = "y";
+        assert_eq!(tree[id(1)], "z");
+        assert_eq!(values(tree.get(id(0)).children()), ["z", "y"]);
+
+        let mut node = tree.add("x");
+        *node = "w";
+        assert_eq!(*node.get_item(), "w");
+    }
+
+    #[test]
+    fn try_get() {
+        let mut tree = sample();
+        assert_eq!(tree.try_get(id(3)).map(|node| *node), Some("c"));
+        tree.remove_subtree(id(3));
+        assert!(tree.try_get(id(3)).is_none());
+        assert!(tree.try_get(id(10)).is_none());
+        assert_eq!(tree.node_count(), 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot access values that haven't been set")]
+    fn stop_access_past_end() {
+        let tree = sample();
+        tree.get(id(6));
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot make a node its own ancestor")]
+    fn stop_cycles() {
+        let mut tree = sample();
+        tree.set_parent(id(1), id(4));
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot make a node its own ancestor")]
+    fn stop_self_parent() {
+        let mut tree = sample();
+        tree.set_parent(id(2), id(2));
+    }
+
+    #[test]
+    fn stale_ids_after_compact() {
+        let mut tree = sample();
+        tree.remove_subtree(id(2));
+        tree.compact();
+
+        // Slots past the compacted end are reused by new nodes
+        let new = tree.add("f").get_id();
+        assert_eq!(new.index(), 4);
+        assert!(tree.try_get(id(4)).is_none());
+        assert_eq!(*tree.get(new), "f");
+
+        // Ids that were already stale aren't remapped to whatever node now holds their slot
+        let remap = tree.compact();
+        assert_eq!(remap.get(id(0)), None);
+        assert_eq!(remap.get(id(4)), None);
+        assert_eq!(remap.get(new).map(NodeId::index), Some(4));
+    }
+
+    #[test]
+    fn validate() {
+        let mut tree = sample();
+        assert_eq!(tree.validate(), Ok(()));
+
+        tree.links[4].parent = Some(2);
+        assert_eq!(tree.validate(), Err(InvariantError::InconsistentLinks(1)));
+
+        let mut tree = sample();
+        tree.links[5].next_sibling = Some(9);
+        assert_eq!(tree.validate(), Err(InvariantError::DanglingLink { index: 5, target: 9 }));
+
+        let mut tree = sample();
+        tree.links[0].parent = Some(3);
+        tree.links[3].first_child = Some(0);
+        tree.links[3].last_child = Some(0);
+        assert_eq!(tree.validate(), Err(InvariantError::Cycle(0)));
+    }
+
+    #[test]
+    fn validate_local() {
+        let mut tree = sample();
+        assert_eq!((0..tree.len).try_for_each(|index| tree.validate_local(index)), Ok(()));
+
+        tree.links[4].parent = Some(2);
+        assert_eq!(tree.validate_local(4), Err(InvariantError::InconsistentLinks(4)));
+        assert_eq!(tree.validate_local(1), Err(InvariantError::InconsistentLinks(1)));
+        // Only the touched node and what it links to are checked
+        assert_eq!(tree.validate_local(5), Ok(()));
+
+        let mut tree = sample();
+        tree.links[5].next_sibling = Some(9);
+        assert_eq!(tree.validate_local(5), Err(InvariantError::DanglingLink { index: 5, target: 9 }));
+    }
+
+    #[test]
+    fn render_ascii() {
+        let tree = sample();
+        let rendered = tree.render_ascii(id(0), |value| value.to_string());
+        assert_eq!(rendered, "root\n|-- a\n|   |-- c\n|   `-- d\n`-- b\n    `-- e\n");
+        assert_eq!(tree.render_ascii(id(2), |value| value.to_string()), "b\n`-- e\n");
+    }
+
+    #[test]
+    fn render_dot() {
+        let mut tree = sample();
+        tree[id(5)] = "say \"hi\"";
+        tree.detach(id(1));
+        let rendered = tree.render_dot(id(0), |value| value.to_string());
+        assert_eq!(rendered, concat!(
+            "digraph {\n",
+            "    node [shape=box];\n",
+            "    n0 [label=\"root\"];\n",
+            "    n2 [label=\"b\"];\n",
+            "    n5 [label=\"say \\\"hi\\\"\"];\n",
+            "    n0 -> n2;\n",
+            "    n2 -> n5;\n",
+            "}\n",
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serde_round_trip() {
+        let mut tree = sample();
+        tree.remove_subtree(id(3));
+        let json = serde_json::to_string(&tree).unwrap();
+        let loaded: FlatTree<String> = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.render_ascii(id(0), String::clone), tree.render_ascii(id(0), |value| value.to_string()));
+        assert!(loaded.try_get(id(3)).is_none());
+
+        // Ids stay valid across the round trip
+        let e = tree.get(id(5)).get_id();
+        let json = serde_json::to_string(&e).unwrap();
+        assert_eq!(loaded[serde_json::from_str::<NodeId>(&json).unwrap()], "e");
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serde_rejects_broken_trees() {
+        let mut json = serde_json::to_value(sample()).unwrap();
+        json["links"][0]["parent"] = serde_json::json!(4);
+        let err = serde_json::from_value::<FlatTree<String>>(json).unwrap_err();
+        assert!(err.to_string().contains("links of node 0 are inconsistent"), "{err}");
+    }
+
+    /// Xorshift generator, so random tests are reproducible without extra dependencies
+    struct Rng(u64);
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+    }
+
+    /// Naive tree kept alongside a [`FlatTree`], indexed by node value
+    #[derive(Default)]
+    struct Model {
+        ids: Vec<Option<NodeId>>,
+        parents: Vec<Option<usize>>,
+        children: Vec<Vec<usize>>,
+    }
+    impl Model {
+        fn add(&mut self, id: NodeId) -> usize {
+            self.ids.push(Some(id));
+            self.parents.push(None);
+            self.children.push(Vec::new());
+            self.ids.len() - 1
+        }
+
+        fn live(&self) -> Vec<usize> {
+            (0..self.ids.len()).filter(|&value| self.ids[value].is_some()).collect()
+        }
+
+        fn detach(&mut self, value: usize) {
+            if let Some(parent) = self.parents[value].take() {
+                self.children[parent].retain(|&child| child != value);
+            }
+        }
+
+        fn set_parent(&mut self, value: usize, parent: usize) {
+            self.detach(value);
+            self.parents[value] = Some(parent);
+            self.children[parent].push(value);
+        }
+
+        fn is_ancestor(&self, ancestor: usize, mut value: usize) -> bool {
+            while let Some(parent) = self.parents[value] {
+                if parent == ancestor {
+                    return true;
+                }
+                value = parent;
+            }
+            false
+        }
+
+        fn pre_order(&self, value: usize, order: &mut Vec<usize>) {
+            order.push(value);
+            for &child in &self.children[value] {
+                self.pre_order(child, order);
+            }
+        }
+
+        fn post_order(&self, value: usize, order: &mut Vec<usize>) {
+            for &child in &self.children[value] {
+                self.post_order(child, order);
+            }
+            order.push(value);
+        }
+
+        fn remove_subtree(&mut self, value: usize) -> Vec<usize> {
+            self.detach(value);
+            let mut removed = Vec::new();
+            self.pre_order(value, &mut removed);
+            for &value in &removed {
+                self.ids[value] = None;
+                self.children[value].clear();
+            }
+            removed
+        }
+
+        /// Checks every live node of the tree against the model
+        fn check(&self, tree: &FlatTree<usize>) {
+            assert_eq!(tree.validate(), Ok(()));
+            for (value, id) in self.ids.iter().enumerate() {
+                let Some(id) = *id else {
+                    continue;
+                };
+                let node = tree.get(id);
+                assert_eq!(*node, value);
+                assert_eq!(node.get_parent().map(|parent| *parent), self.parents[value]);
+
+                let children: Vec<usize> = node.children().map(|child| *child).collect();
+                assert_eq!(children, self.children[value]);
+
+                let mut pre_order = Vec::new();
+                self.pre_order(value, &mut pre_order);
+                assert_eq!(node.pre_order().map(|node| *node).collect::<Vec<_>>(), pre_order);
+
+                let mut post_order = Vec::new();
+                self.post_order(value, &mut post_order);
+                assert_eq!(node.post_order().map(|node| *node).collect::<Vec<_>>(), post_order);
+
+                let mut breadth_first: Vec<usize> = node.breadth_first().map(|node| *node).collect();
+                breadth_first.sort_unstable();
+                pre_order.sort_unstable();
+                assert_eq!(breadth_first, pre_order);
+            }
+        }
+    }
+
+    #[test]
+    fn random_edits_match_model() {
+        for seed in 1..=200 {
+            let mut rng = Rng(seed);
+            let mut tree = FlatTree::new();
+            let mut model = Model::default();
+            let mut removed = Vec::new();
+
+            for _ in 0..150 {
+                let live = model.live();
+                let operation = if live.is_empty() { 0 } else { rng.below(7) };
+                let mut pick = || live[rng.below(live.len())];
+                match operation {
+                    1 => {
+                        let (value, parent) = (pick(), pick());
+                        if value != parent && !model.is_ancestor(value, parent) {
+                            tree.set_parent(model.ids[value].unwrap(), model.ids[parent].unwrap());
+                            model.set_parent(value, parent);
+                        }
+                    },
+                    2 => {
+                        let value = pick();
+                        tree.detach(model.ids[value].unwrap());
+                        model.detach(value);
+                    },
+                    3 => {
+                        let value = pick();
+                        removed.push(model.ids[value].unwrap());
+                        let values = tree.remove_subtree(model.ids[value].unwrap());
+                        assert_eq!(values, model.remove_subtree(value));
+                    },
+                    4 => {
+                        let (value, replacement) = (pick(), pick());
+                        if value == replacement || model.is_ancestor(value, replacement) || model.is_ancestor(replacement, value) {
+                            continue;
+                        }
+                        let id = model.ids[value].unwrap();
+                        let values = tree.replace_with(id, model.ids[replacement].unwrap());
+                        removed.push(id);
+
+                        model.detach(replacement);
+                        if let Some(parent) = model.parents[value] {
+                            let position = model.children[parent].iter().position(|&child| child == value).unwrap();
+                            model.children[parent][position] = replacement;
+                            model.parents[replacement] = Some(parent);
+                            model.parents[value] = None;
+                        }
+                        assert_eq!(values, model.remove_subtree(value));
+                    },
+                    5 => {
+                        let under = pick();
+                        let mut other = FlatTree::new();
+                        let mut values = Vec::new();
+                        for index in 0..rng.below(4) {
+                            let value = model.ids.len() + index;
+                            let id = other.add(value).get_id();
+                            if index > 0 && rng.below(2) == 0 {
+                                other.set_parent(id, values[rng.below(index)]);
+                            }
+                            values.push(id);
+                        }
+
+                        let parents: Vec<Option<usize>> = values.iter()
+                            .map(|&id| other.get(id).get_parent().map(|parent| *parent))
+                            .collect();
+                        let remap = tree.graft(other, model.ids[under].unwrap());
+                        for (id, parent) in values.into_iter().zip(parents) {
+                            let value = model.add(remap.get(id).unwrap());
+                            model.set_parent(value, parent.unwrap_or(under));
+                        }
+                    },
+                    6 => {
+                        let remap = tree.compact();
+                        for id in model.ids.iter_mut().flatten() {
+                            *id = remap.get(*id).unwrap();
+                        }
+                    },
+                    _ => {
+                        let id = tree.add(model.ids.len()).get_id();
+                        model.add(id);
+                    },
+                }
+
+                model.check(&tree);
+                assert!(removed.iter().all(|&id| !tree.contains(id)));
+            }
+        }
     }
 }
@@ -0,0 +1,60 @@
+use std::fmt::Write;
+
+use crate::{Children, FlatTree, NodeId, TreeNode};
+
+impl<T> FlatTree<T> {
+    /// Draws the subtree at `root` as indented ASCII, one node per line
+    ///
+    /// ```text
+    /// root
+    /// |-- a
+    /// |   `-- c
+    /// `-- b
+    /// ```
+    pub fn render_ascii(&self, root: NodeId, label: impl Fn(&T) -> String) -> String {
+        let mut output = label(&self[root]);
+        output.push('\n');
+        self.render_children(&mut output, self.index_of(root), "", &label);
+        output
+    }
+
+    fn render_children(&self, output: &mut String, index: usize, prefix: &str, label: &impl Fn(&T) -> String) {
+        let mut children = Children::new(self, index).peekable();
+        while let Some(child) = children.next() {
+            let last = children.peek().is_none();
+            let (branch, indent) = if last { ("`-- ", "    ") } else { ("|-- ", "|   ") };
+            let _ = writeln!(output, "{prefix}{branch}{}", label(child.get_item()));
+            self.render_children(output, child.get_index(), &format!("{prefix}{indent}"), label);
+        }
+    }
+
+    /// Exports the subtree at `root` as a Graphviz DOT digraph, naming each node after its index
+    pub fn render_dot(&self, root: NodeId, label: impl Fn(&T) -> String) -> String {
+        let mut output = String::from("digraph {\n    node [shape=box];\n");
+        let root = self.get(root);
+        for node in root.pre_order() {
+            let _ = writeln!(output, "    n{} [label=\"{}\"];", node.get_index(), escape_dot(&label(node.get_item())));
+        }
+        for node in root.pre_order() {
+            for child in node.children() {
+                let _ = writeln!(output, "    n{} -> n{};", node.get_index(), child.get_index());
+            }
+        }
+        output.push_str("}\n");
+        output
+    }
+}
+
+/// Escapes a label for use inside a quoted DOT string
+fn escape_dot(label: &str) -> String {
+    let mut escaped = String::with_capacity(label.len());
+    for ch in label.chars() {
+        match ch {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            ch => escaped.push(ch),
+        }
+    }
+    escaped
+}